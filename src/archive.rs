@@ -0,0 +1,657 @@
+use crate::date::dos_date_time;
+use flate2::write::{DeflateEncoder, GzEncoder};
+use flate2::{Compression, Crc};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const BLOCK: usize = 512;
+// Largest size and offset the classic zip fields can hold
+const ZIP32_MAX: u64 = 0xFFFF_FFFF;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl ArchiveFormat {
+    pub fn from_string(format: &str) -> Option<Self> {
+        match format {
+            "tar" => Some(ArchiveFormat::Tar),
+            "tar.gz" | "tgz" => Some(ArchiveFormat::TarGz),
+            "zip" => Some(ArchiveFormat::Zip),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::Tar => "tar",
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::Zip => "zip",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ArchiveFormat::Tar => "application/x-tar",
+            ArchiveFormat::TarGz => "application/gzip",
+            ArchiveFormat::Zip => "application/zip",
+        }
+    }
+}
+
+struct Entry {
+    // Path inside the archive, with `/` separators
+    name: String,
+    path: PathBuf,
+    is_dir: bool,
+    size: u64,
+    modified: SystemTime,
+}
+
+// Writes an archive of `dir` to `out` as it walks the tree, with every entry
+// under a top-level directory called `name`. Like listings, dotfiles are left
+// out; so are symlinks, which keeps the archive within the tree.
+pub fn write_archive(
+    format: ArchiveFormat,
+    dir: &Path,
+    name: &str,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let mut entries = Vec::new();
+    collect(dir, name, &mut entries)?;
+    match format {
+        ArchiveFormat::Tar => write_tar(&entries, out),
+        ArchiveFormat::TarGz => {
+            let mut gzip = GzEncoder::new(out, Compression::default());
+            write_tar(&entries, &mut gzip)?;
+            gzip.finish()?;
+            Ok(())
+        }
+        ArchiveFormat::Zip => write_zip(&entries, out),
+    }
+}
+
+fn collect(dir: &Path, name: &str, entries: &mut Vec<Entry>) -> io::Result<()> {
+    let metadata = std::fs::metadata(dir)?;
+    entries.push(Entry {
+        name: format!("{}/", name),
+        path: dir.to_path_buf(),
+        is_dir: true,
+        size: 0,
+        modified: metadata.modified().unwrap_or(UNIX_EPOCH),
+    });
+
+    let mut children: Vec<_> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| Some((entry.file_name().into_string().ok()?, entry)))
+        .filter(|(child, _)| !child.starts_with('.'))
+        .collect();
+    children.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    for (child, entry) in children {
+        let file_type = entry.file_type()?;
+        let child_name = format!("{}/{}", name, child);
+        if file_type.is_dir() {
+            collect(&entry.path(), &child_name, entries)?;
+        } else if file_type.is_file() {
+            let metadata = entry.metadata()?;
+            entries.push(Entry {
+                name: child_name,
+                path: entry.path(),
+                is_dir: false,
+                size: metadata.len(),
+                modified: metadata.modified().unwrap_or(UNIX_EPOCH),
+            });
+        }
+    }
+    Ok(())
+}
+
+// Copies exactly `size` bytes of a file, padding with zeros if it shrank
+// since it was listed, as the size is already part of the archive
+fn copy_file(entry: &Entry, out: &mut dyn Write) -> io::Result<()> {
+    let file = File::open(&entry.path)?;
+    let copied = io::copy(&mut file.take(entry.size), out)?;
+    io::copy(&mut io::repeat(0).take(entry.size - copied), out)?;
+    Ok(())
+}
+
+fn write_tar(entries: &[Entry], out: &mut dyn Write) -> io::Result<()> {
+    for entry in entries {
+        let mtime = entry
+            .modified
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        // Names and sizes that don't fit the ustar header go in a pax
+        // extended header in front of the entry
+        let mut pax = String::new();
+        let (prefix, name) = split_ustar_name(&entry.name).unwrap_or_else(|| {
+            pax.push_str(&pax_record("path", &entry.name));
+            ("", truncate(&entry.name, 100))
+        });
+        if entry.size > 0o777_7777_7777 {
+            pax.push_str(&pax_record("size", &entry.size.to_string()));
+        }
+        if !pax.is_empty() {
+            let header = tar_header("././@PaxHeader", "", b'x', pax.len() as u64, mtime, 0o644);
+            out.write_all(&header)?;
+            out.write_all(pax.as_bytes())?;
+            out.write_all(&[0; BLOCK][..padding(pax.len() as u64)])?;
+        }
+
+        let (kind, mode) = if entry.is_dir {
+            (b'5', 0o755)
+        } else {
+            (b'0', 0o644)
+        };
+        out.write_all(&tar_header(name, prefix, kind, entry.size, mtime, mode))?;
+        if !entry.is_dir {
+            copy_file(entry, out)?;
+            out.write_all(&[0; BLOCK][..padding(entry.size)])?;
+        }
+    }
+    // Two zero blocks end the archive
+    out.write_all(&[0; BLOCK * 2])
+}
+
+fn tar_header(name: &str, prefix: &str, kind: u8, size: u64, mtime: u64, mode: u32) -> [u8; BLOCK] {
+    let mut header = [0u8; BLOCK];
+    put(&mut header[0..100], name.as_bytes());
+    put(&mut header[100..108], format!("{:07o}", mode).as_bytes());
+    put(&mut header[108..116], b"0000000");
+    put(&mut header[116..124], b"0000000");
+    // Sizes too large for 11 octal digits are carried by the pax header
+    put(
+        &mut header[124..136],
+        format!("{:011o}", size.min(0o777_7777_7777)).as_bytes(),
+    );
+    put(
+        &mut header[136..148],
+        format!("{:011o}", mtime.min(0o777_7777_7777)).as_bytes(),
+    );
+    header[156] = kind;
+    put(&mut header[257..263], b"ustar\0");
+    put(&mut header[263..265], b"00");
+    put(&mut header[345..500], prefix.as_bytes());
+
+    // The checksum is computed with its own field set to spaces
+    header[148..156].copy_from_slice(b"        ");
+    let checksum: u32 = header.iter().map(|&b| b as u32).sum();
+    put(
+        &mut header[148..156],
+        format!("{:06o}\0 ", checksum).as_bytes(),
+    );
+    header
+}
+
+// Splits a name into the ustar prefix and name fields, at a `/`
+fn split_ustar_name(name: &str) -> Option<(&str, &str)> {
+    if name.len() <= 100 {
+        return Some(("", name));
+    }
+    name.match_indices('/')
+        .map(|(i, _)| (&name[..i], &name[i + 1..]))
+        .find(|(prefix, rest)| prefix.len() <= 155 && rest.len() <= 100 && !rest.is_empty())
+}
+
+// A pax record is prefixed with its own length in decimal, which includes
+// the digits of that length
+fn pax_record(key: &str, value: &str) -> String {
+    let base = key.len() + value.len() + 3;
+    let mut len = base + 1;
+    while len != base + len.to_string().len() {
+        len = base + len.to_string().len();
+    }
+    format!("{} {}={}\n", len, key, value)
+}
+
+fn truncate(name: &str, max: usize) -> &str {
+    let mut end = name.len().min(max);
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    &name[..end]
+}
+
+fn put(field: &mut [u8], value: &[u8]) {
+    let len = value.len().min(field.len());
+    field[..len].copy_from_slice(&value[..len]);
+}
+
+fn padding(size: u64) -> usize {
+    (BLOCK - (size % BLOCK as u64) as usize) % BLOCK
+}
+
+// Counts what is written, for the offsets the zip central directory records
+struct Counter<'a> {
+    inner: &'a mut dyn Write,
+    written: u64,
+}
+
+impl Write for Counter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Computes the CRC-32 of what passes through to the deflater
+struct CrcWriter<W: Write> {
+    inner: W,
+    crc: Crc,
+}
+
+impl<W: Write> Write for CrcWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.crc.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+struct ZipRecord {
+    name: String,
+    is_dir: bool,
+    crc: u32,
+    compressed: u64,
+    size: u64,
+    offset: u64,
+    date: u16,
+    time: u16,
+    zip64: bool,
+}
+
+// Writes a zip archive in one pass: sizes and CRCs follow each entry in a
+// data descriptor, as they aren't known until the entry has been compressed.
+// Zip64 fields are used for entries and offsets beyond 4 GiB.
+fn write_zip(entries: &[Entry], out: &mut dyn Write) -> io::Result<()> {
+    let mut out = Counter {
+        inner: out,
+        written: 0,
+    };
+    let mut records = Vec::with_capacity(entries.len());
+
+    for entry in entries {
+        let (date, time) = dos_date_time(entry.modified);
+        // Decided before compressing, from the uncompressed size, leaving room
+        // for data that deflate makes slightly larger
+        let zip64 = entry.size >= ZIP32_MAX - (ZIP32_MAX >> 8);
+        let mut record = ZipRecord {
+            name: entry.name.clone(),
+            is_dir: entry.is_dir,
+            crc: 0,
+            compressed: 0,
+            size: 0,
+            offset: out.written,
+            date,
+            time,
+            zip64,
+        };
+
+        let mut header = Vec::with_capacity(30 + entry.name.len() + 20);
+        header.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        header.extend_from_slice(&zip_version(zip64).to_le_bytes());
+        header.extend_from_slice(&zip_flags(entry.is_dir).to_le_bytes());
+        header.extend_from_slice(&zip_method(entry.is_dir).to_le_bytes());
+        header.extend_from_slice(&time.to_le_bytes());
+        header.extend_from_slice(&date.to_le_bytes());
+        // CRC and sizes come in the data descriptor
+        header.extend_from_slice(&0u32.to_le_bytes());
+        let placeholder: u32 = if zip64 { 0xFFFF_FFFF } else { 0 };
+        header.extend_from_slice(&placeholder.to_le_bytes());
+        header.extend_from_slice(&placeholder.to_le_bytes());
+        header.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+        header.extend_from_slice(&(if zip64 { 20u16 } else { 0 }).to_le_bytes());
+        header.extend_from_slice(entry.name.as_bytes());
+        if zip64 {
+            header.extend_from_slice(&1u16.to_le_bytes());
+            header.extend_from_slice(&16u16.to_le_bytes());
+            header.extend_from_slice(&[0; 16]);
+        }
+        out.write_all(&header)?;
+
+        if !entry.is_dir {
+            let start = out.written;
+            record.crc = deflate_file(entry, &mut out)?;
+            // copy_file writes exactly the listed size
+            record.size = entry.size;
+            record.compressed = out.written - start;
+
+            let mut descriptor = Vec::with_capacity(24);
+            descriptor.extend_from_slice(&0x08074b50u32.to_le_bytes());
+            descriptor.extend_from_slice(&record.crc.to_le_bytes());
+            if zip64 {
+                descriptor.extend_from_slice(&record.compressed.to_le_bytes());
+                descriptor.extend_from_slice(&record.size.to_le_bytes());
+            } else {
+                descriptor.extend_from_slice(&(record.compressed as u32).to_le_bytes());
+                descriptor.extend_from_slice(&(record.size as u32).to_le_bytes());
+            }
+            out.write_all(&descriptor)?;
+        }
+        records.push(record);
+    }
+
+    let directory_offset = out.written;
+    for record in &records {
+        write_central_record(record, &mut out)?;
+    }
+    let directory_size = out.written - directory_offset;
+    write_end_of_directory(
+        records.len() as u64,
+        directory_size,
+        directory_offset,
+        &mut out,
+    )?;
+    out.flush()
+}
+
+// Writes the deflated file, returning the CRC-32 of its content
+fn deflate_file(entry: &Entry, out: &mut dyn Write) -> io::Result<u32> {
+    let mut deflate = CrcWriter {
+        inner: DeflateEncoder::new(out, Compression::default()),
+        crc: Crc::new(),
+    };
+    copy_file(entry, &mut deflate)?;
+    deflate.inner.finish()?;
+    Ok(deflate.crc.sum())
+}
+
+fn write_central_record(record: &ZipRecord, out: &mut dyn Write) -> io::Result<()> {
+    // Zip64 extra field values, in the order the spec lists them, for each
+    // field that doesn't fit in 32 bits
+    let mut extra = Vec::new();
+    let sizes_zip64 = record.zip64 || record.compressed >= ZIP32_MAX || record.size >= ZIP32_MAX;
+    if sizes_zip64 {
+        extra.extend_from_slice(&record.size.to_le_bytes());
+        extra.extend_from_slice(&record.compressed.to_le_bytes());
+    }
+    let offset_zip64 = record.offset >= ZIP32_MAX;
+    if offset_zip64 {
+        extra.extend_from_slice(&record.offset.to_le_bytes());
+    }
+    let zip64 = sizes_zip64 || offset_zip64;
+
+    let mut header = Vec::with_capacity(46 + record.name.len() + 4 + extra.len());
+    header.extend_from_slice(&0x02014b50u32.to_le_bytes());
+    // Made by Unix, spec version 4.5
+    header.extend_from_slice(&(3u16 << 8 | 45).to_le_bytes());
+    header.extend_from_slice(&zip_version(zip64).to_le_bytes());
+    header.extend_from_slice(&zip_flags(record.is_dir).to_le_bytes());
+    header.extend_from_slice(&zip_method(record.is_dir).to_le_bytes());
+    header.extend_from_slice(&record.time.to_le_bytes());
+    header.extend_from_slice(&record.date.to_le_bytes());
+    header.extend_from_slice(&record.crc.to_le_bytes());
+    if sizes_zip64 {
+        header.extend_from_slice(&0xFFFF_FFFFu32.to_le_bytes());
+        header.extend_from_slice(&0xFFFF_FFFFu32.to_le_bytes());
+    } else {
+        header.extend_from_slice(&(record.compressed as u32).to_le_bytes());
+        header.extend_from_slice(&(record.size as u32).to_le_bytes());
+    }
+    header.extend_from_slice(&(record.name.len() as u16).to_le_bytes());
+    let extra_len = if zip64 { extra.len() as u16 + 4 } else { 0 };
+    header.extend_from_slice(&extra_len.to_le_bytes());
+    // Comment length, disk number and internal attributes
+    header.extend_from_slice(&[0; 6]);
+    // Unix permissions in the high half; 0x10 is the MS-DOS directory flag
+    let attributes: u32 = if record.is_dir {
+        (0o40755 << 16) | 0x10
+    } else {
+        0o100644 << 16
+    };
+    header.extend_from_slice(&attributes.to_le_bytes());
+    let offset = if offset_zip64 {
+        0xFFFF_FFFF
+    } else {
+        record.offset as u32
+    };
+    header.extend_from_slice(&offset.to_le_bytes());
+    header.extend_from_slice(record.name.as_bytes());
+    if zip64 {
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+        header.extend_from_slice(&extra);
+    }
+    out.write_all(&header)
+}
+
+fn write_end_of_directory(count: u64, size: u64, offset: u64, out: &mut Counter) -> io::Result<()> {
+    let mut end = Vec::with_capacity(98);
+    if count >= 0xFFFF || size >= ZIP32_MAX || offset >= ZIP32_MAX {
+        let record_offset = out.written;
+        // Zip64 end of central directory record
+        end.extend_from_slice(&0x06064b50u32.to_le_bytes());
+        end.extend_from_slice(&44u64.to_le_bytes());
+        end.extend_from_slice(&(3u16 << 8 | 45).to_le_bytes());
+        end.extend_from_slice(&45u16.to_le_bytes());
+        end.extend_from_slice(&[0; 8]);
+        end.extend_from_slice(&count.to_le_bytes());
+        end.extend_from_slice(&count.to_le_bytes());
+        end.extend_from_slice(&size.to_le_bytes());
+        end.extend_from_slice(&offset.to_le_bytes());
+        // and its locator
+        end.extend_from_slice(&0x07064b50u32.to_le_bytes());
+        end.extend_from_slice(&0u32.to_le_bytes());
+        end.extend_from_slice(&record_offset.to_le_bytes());
+        end.extend_from_slice(&1u32.to_le_bytes());
+    }
+    end.extend_from_slice(&0x06054b50u32.to_le_bytes());
+    end.extend_from_slice(&[0; 4]);
+    let count = count.min(0xFFFF) as u16;
+    end.extend_from_slice(&count.to_le_bytes());
+    end.extend_from_slice(&count.to_le_bytes());
+    end.extend_from_slice(&(size.min(ZIP32_MAX) as u32).to_le_bytes());
+    end.extend_from_slice(&(offset.min(ZIP32_MAX) as u32).to_le_bytes());
+    end.extend_from_slice(&0u16.to_le_bytes());
+    out.write_all(&end)
+}
+
+fn zip_version(zip64: bool) -> u16 {
+    if zip64 {
+        45
+    } else {
+        20
+    }
+}
+
+// Names are UTF-8 (bit 11); file entries have a data descriptor (bit 3)
+fn zip_flags(is_dir: bool) -> u16 {
+    if is_dir {
+        0x0800
+    } else {
+        0x0808
+    }
+}
+
+// Deflate for files, stored for directories
+fn zip_method(is_dir: bool) -> u16 {
+    if is_dir {
+        0
+    } else {
+        8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::{DeflateDecoder, GzDecoder};
+
+    // A directory with a nested file, a dotfile and a name too long for ustar
+    fn tree(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("archive-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("a.txt"), "hello").unwrap();
+        std::fs::write(dir.join("sub/b.bin"), vec![7u8; 1000]).unwrap();
+        std::fs::write(dir.join(".hidden"), "secret").unwrap();
+        std::fs::write(dir.join("l".repeat(120)), "long").unwrap();
+        dir
+    }
+
+    fn archive(format: ArchiveFormat, dir: &Path) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_archive(format, dir, "top", &mut out).unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+        out
+    }
+
+    fn octal(field: &[u8]) -> u64 {
+        let digits = std::str::from_utf8(field).unwrap();
+        u64::from_str_radix(digits.trim_matches(|c| c == '\0' || c == ' '), 8).unwrap()
+    }
+
+    fn text(field: &[u8]) -> String {
+        let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+        String::from_utf8(field[..end].to_vec()).unwrap()
+    }
+
+    // Reads back (name, kind, content) for each entry, applying pax paths
+    fn read_tar(tar: &[u8]) -> Vec<(String, u8, Vec<u8>)> {
+        assert_eq!(tar.len() % BLOCK, 0);
+        let mut entries = Vec::new();
+        let mut pax_path = None;
+        let mut pos = 0;
+        while tar[pos..pos + BLOCK].iter().any(|&b| b != 0) {
+            let header = &tar[pos..pos + BLOCK];
+            let mut blank = header.to_vec();
+            blank[148..156].copy_from_slice(b"        ");
+            let sum: u64 = blank.iter().map(|&b| b as u64).sum();
+            assert_eq!(octal(&header[148..156]), sum);
+            assert_eq!(&header[257..265], b"ustar\x0000");
+
+            let size = octal(&header[124..136]) as usize;
+            let data = tar[pos + BLOCK..pos + BLOCK + size].to_vec();
+            pos += BLOCK + size + padding(size as u64);
+            let name = match text(&header[345..500]) {
+                prefix if prefix.is_empty() => text(&header[0..100]),
+                prefix => format!("{}/{}", prefix, text(&header[0..100])),
+            };
+            if header[156] == b'x' {
+                let record = String::from_utf8(data).unwrap();
+                let (len, rest) = record.split_once(' ').unwrap();
+                assert_eq!(len.parse::<usize>().unwrap(), record.len());
+                pax_path = rest.strip_prefix("path=").map(|path| path.trim_end().to_string());
+                continue;
+            }
+            entries.push((pax_path.take().unwrap_or(name), header[156], data));
+        }
+        assert!(tar[pos..].iter().all(|&b| b == 0));
+        assert_eq!(tar.len() - pos, BLOCK * 2);
+        entries
+    }
+
+    fn expected() -> Vec<(String, u8, Vec<u8>)> {
+        vec![
+            ("top/".to_string(), b'5', Vec::new()),
+            ("top/a.txt".to_string(), b'0', b"hello".to_vec()),
+            (format!("top/{}", "l".repeat(120)), b'0', b"long".to_vec()),
+            ("top/sub/".to_string(), b'5', Vec::new()),
+            ("top/sub/b.bin".to_string(), b'0', vec![7u8; 1000]),
+        ]
+    }
+
+    #[test]
+    fn tar_round_trip() {
+        let tar = archive(ArchiveFormat::Tar, &tree("tar"));
+        assert_eq!(read_tar(&tar), expected());
+    }
+
+    #[test]
+    fn tar_gz_round_trip() {
+        let gz = archive(ArchiveFormat::TarGz, &tree("tgz"));
+        let mut tar = Vec::new();
+        GzDecoder::new(gz.as_slice()).read_to_end(&mut tar).unwrap();
+        assert_eq!(read_tar(&tar), expected());
+    }
+
+    fn u16_at(data: &[u8], pos: usize) -> usize {
+        u16::from_le_bytes([data[pos], data[pos + 1]]) as usize
+    }
+
+    fn u32_at(data: &[u8], pos: usize) -> u32 {
+        u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn zip_round_trip() {
+        let zip = archive(ArchiveFormat::Zip, &tree("zip"));
+        let end = zip.len() - 22;
+        assert_eq!(u32_at(&zip, end), 0x06054b50);
+        let count = u16_at(&zip, end + 10);
+        let mut pos = u32_at(&zip, end + 16) as usize;
+        assert_eq!(pos + u32_at(&zip, end + 12) as usize, end);
+
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            assert_eq!(u32_at(&zip, pos), 0x02014b50);
+            let method = u16_at(&zip, pos + 10);
+            let crc = u32_at(&zip, pos + 16);
+            let compressed = u32_at(&zip, pos + 20) as usize;
+            let size = u32_at(&zip, pos + 24) as usize;
+            let name_len = u16_at(&zip, pos + 28);
+            let extra_len = u16_at(&zip, pos + 30);
+            let offset = u32_at(&zip, pos + 42) as usize;
+            let name = String::from_utf8(zip[pos + 46..pos + 46 + name_len].to_vec()).unwrap();
+            pos += 46 + name_len + extra_len;
+
+            // The local header names the same entry
+            assert_eq!(u32_at(&zip, offset), 0x04034b50);
+            assert_eq!(&zip[offset + 30..offset + 30 + name_len], name.as_bytes());
+            let start = offset + 30 + name_len + u16_at(&zip, offset + 28);
+            let mut data = Vec::new();
+            if method == 8 {
+                DeflateDecoder::new(&zip[start..start + compressed])
+                    .read_to_end(&mut data)
+                    .unwrap();
+                // followed by its data descriptor
+                let descriptor = start + compressed;
+                assert_eq!(u32_at(&zip, descriptor), 0x08074b50);
+                assert_eq!(u32_at(&zip, descriptor + 4), crc);
+            }
+            assert_eq!(data.len(), size);
+            let mut check = Crc::new();
+            check.update(&data);
+            assert_eq!(check.sum(), crc);
+            let kind = if name.ends_with('/') { b'5' } else { b'0' };
+            entries.push((name, kind, data));
+        }
+        assert_eq!(entries, expected());
+    }
+
+    #[test]
+    fn ustar_names() {
+        assert_eq!(split_ustar_name("short"), Some(("", "short")));
+        let long = format!("{}/{}", "p".repeat(150), "n".repeat(100));
+        assert_eq!(split_ustar_name(&long), Some((&long[..150], &long[151..])));
+        assert_eq!(split_ustar_name(&"n".repeat(101)), None);
+    }
+
+    #[test]
+    fn pax_records_count_their_own_length() {
+        assert_eq!(pax_record("path", "a"), "9 path=a\n");
+        assert_eq!(pax_record("path", "abc"), "12 path=abc\n");
+        // A length of 100 would need three digits, making it 101
+        let record = pax_record("path", &"x".repeat(90));
+        assert_eq!((record.len(), &record[..3]), (99, "99 "));
+        let record = pax_record("path", &"x".repeat(91));
+        assert_eq!((record.len(), &record[..4]), (101, "101 "));
+    }
+}
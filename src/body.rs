@@ -0,0 +1,330 @@
+use std::io::{self, BufRead, Error, ErrorKind, Read, Write};
+
+// Chunk-size lines longer than this are rejected
+const MAX_CHUNK_LINE: usize = 4096;
+
+enum Framing {
+    Empty,
+    Length(u64),
+    Chunked(ChunkState),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ChunkState {
+    // Expecting a chunk-size line
+    Size,
+    // Inside a chunk with this many bytes left
+    Data(u64),
+    // Expecting the CRLF that ends a chunk
+    DataEnd,
+    Done,
+}
+
+// A request body that is read lazily from the connection, decoding either
+// Content-Length or chunked framing. Nothing is read until a handler asks.
+pub struct Body {
+    reader: Option<Box<dyn BufRead + Send>>,
+    framing: Framing,
+    // Where to send `100 Continue` before the first read, for clients that
+    // sent `Expect: 100-continue` and are waiting for it
+    interim: Option<Box<dyn Write + Send>>,
+    // Reads fail with FileTooLarge once more than this has been decoded
+    limit: Option<u64>,
+    read: u64,
+}
+
+impl Body {
+    pub fn empty() -> Body {
+        Body {
+            reader: None,
+            framing: Framing::Empty,
+            interim: None,
+            limit: None,
+            read: 0,
+        }
+    }
+
+    pub fn with_length(reader: Box<dyn BufRead + Send>, length: u64) -> Body {
+        Body {
+            reader: Some(reader),
+            framing: Framing::Length(length),
+            interim: None,
+            limit: None,
+            read: 0,
+        }
+    }
+
+    pub fn chunked(reader: Box<dyn BufRead + Send>) -> Body {
+        Body {
+            reader: Some(reader),
+            framing: Framing::Chunked(ChunkState::Size),
+            interim: None,
+            limit: None,
+            read: 0,
+        }
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> Body {
+        let length = bytes.len() as u64;
+        Body::with_length(Box::new(io::Cursor::new(bytes)), length)
+    }
+
+    // Caps the decoded size, for bodies whose length isn't known up front
+    pub fn limit(&mut self, max: u64) {
+        self.limit = Some(max);
+    }
+
+    // Defers `100 Continue` until a handler actually reads the body, so one
+    // that answers without it never makes the client send it
+    pub fn continue_on_read(&mut self, writer: Box<dyn Write + Send>) {
+        if !self.is_empty() {
+            self.interim = Some(writer);
+        }
+    }
+
+    fn send_continue(&mut self) -> io::Result<()> {
+        if let Some(mut writer) = self.interim.take() {
+            writer.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    // The number of bytes left when the length is known up front
+    pub fn remaining(&self) -> Option<u64> {
+        match self.framing {
+            Framing::Empty => Some(0),
+            Framing::Length(remaining) => Some(remaining),
+            Framing::Chunked(_) => None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.remaining() == Some(0)
+    }
+
+    pub fn read_to_vec(&mut self) -> io::Result<Vec<u8>> {
+        let mut buffer = Vec::with_capacity(self.remaining().unwrap_or(0).min(1 << 20) as usize);
+        self.read_to_end(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    fn read_chunked(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let reader = match self.reader.as_mut() {
+            Some(reader) => reader,
+            None => return Ok(0),
+        };
+        loop {
+            let state = match self.framing {
+                Framing::Chunked(state) => state,
+                _ => return Ok(0),
+            };
+            match state {
+                ChunkState::Size => {
+                    let line = read_line(reader)?;
+                    let size = line.split(';').next().unwrap_or_default().trim();
+                    let size = u64::from_str_radix(size, 16)
+                        .map_err(|_| Error::new(ErrorKind::InvalidData, "Invalid chunk size"))?;
+                    if size == 0 {
+                        // Skip trailer fields up to the empty line
+                        while !read_line(reader)?.is_empty() {}
+                        self.framing = Framing::Chunked(ChunkState::Done);
+                    } else {
+                        self.framing = Framing::Chunked(ChunkState::Data(size));
+                    }
+                }
+                ChunkState::Data(remaining) => {
+                    let max = buf.len().min(remaining as usize);
+                    let read = reader.read(&mut buf[..max])?;
+                    if read == 0 {
+                        return Err(Error::new(ErrorKind::UnexpectedEof, "Truncated chunk"));
+                    }
+                    let remaining = remaining - read as u64;
+                    self.framing = Framing::Chunked(if remaining == 0 {
+                        ChunkState::DataEnd
+                    } else {
+                        ChunkState::Data(remaining)
+                    });
+                    return Ok(read);
+                }
+                ChunkState::DataEnd => {
+                    if !read_line(reader)?.is_empty() {
+                        return Err(Error::new(ErrorKind::InvalidData, "Missing chunk CRLF"));
+                    }
+                    self.framing = Framing::Chunked(ChunkState::Size);
+                }
+                ChunkState::Done => return Ok(0),
+            }
+        }
+    }
+}
+
+impl Read for Body {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.read_framed(buf)?;
+        self.read += read as u64;
+        match self.limit {
+            Some(limit) if self.read > limit => Err(Error::new(
+                ErrorKind::FileTooLarge,
+                format!("Body exceeds {} bytes", limit),
+            )),
+            _ => Ok(read),
+        }
+    }
+}
+
+impl Body {
+    fn read_framed(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.send_continue()?;
+        match self.framing {
+            Framing::Empty => Ok(0),
+            Framing::Length(0) => Ok(0),
+            Framing::Length(remaining) => {
+                let reader = match self.reader.as_mut() {
+                    Some(reader) => reader,
+                    None => return Ok(0),
+                };
+                let max = buf.len().min(remaining.min(usize::MAX as u64) as usize);
+                let read = reader.read(&mut buf[..max])?;
+                if read == 0 {
+                    return Err(Error::new(ErrorKind::UnexpectedEof, "Truncated body"));
+                }
+                self.framing = Framing::Length(remaining - read as u64);
+                Ok(read)
+            }
+            Framing::Chunked(_) => self.read_chunked(buf),
+        }
+    }
+}
+
+// Reads a CRLF-terminated line, without the terminator
+fn read_line(reader: &mut Box<dyn BufRead + Send>) -> io::Result<String> {
+    let mut line = Vec::new();
+    reader
+        .by_ref()
+        .take(MAX_CHUNK_LINE as u64)
+        .read_until(b'\n', &mut line)?;
+    if line.last() != Some(&b'\n') {
+        return Err(Error::new(ErrorKind::InvalidData, "Invalid chunk line"));
+    }
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::sync::{Arc, Mutex};
+
+    fn chunked(input: &str) -> Body {
+        Body::chunked(Box::new(Cursor::new(input.as_bytes().to_vec())))
+    }
+
+    // Records what is written to it, for checking interim responses
+    #[derive(Clone, Default)]
+    struct Sink(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn decodes_chunked_bodies() {
+        let mut body = chunked("5;ext=1\r\nhello\r\n7\r\n, world\r\n0\r\nTrailer: x\r\n\r\nnext");
+        assert_eq!(body.remaining(), None);
+        assert_eq!(body.read_to_vec().unwrap(), b"hello, world");
+        assert_eq!(body.read(&mut [0; 8]).unwrap(), 0);
+
+        assert_eq!(
+            chunked("A\nabcdefghij\n0\n\n").read_to_vec().unwrap(),
+            b"abcdefghij"
+        );
+        assert!(chunked("0\r\n\r\n").read_to_vec().unwrap().is_empty());
+    }
+
+    #[test]
+    fn refuses_malformed_chunks() {
+        let cases = [
+            ("z\r\n", ErrorKind::InvalidData),
+            ("5\r\nhelloX\r\n0\r\n\r\n", ErrorKind::InvalidData),
+            ("5\r\nhel", ErrorKind::UnexpectedEof),
+            ("5\r\nhello\r\n", ErrorKind::InvalidData),
+        ];
+        for (input, kind) in cases {
+            assert_eq!(
+                chunked(input).read_to_vec().unwrap_err().kind(),
+                kind,
+                "{:?}",
+                input
+            );
+        }
+        let long = format!("{}1\r\n", "0".repeat(MAX_CHUNK_LINE));
+        assert_eq!(
+            chunked(&long).read_to_vec().unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn reads_exactly_the_content_length() {
+        let mut body = Body::with_length(Box::new(Cursor::new(b"abcdef".to_vec())), 4);
+        assert_eq!(body.remaining(), Some(4));
+        assert_eq!(body.read_to_vec().unwrap(), b"abcd");
+        assert!(body.is_empty());
+
+        let mut body = Body::with_length(Box::new(Cursor::new(b"ab".to_vec())), 4);
+        assert_eq!(
+            body.read_to_vec().unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn limits_the_decoded_size() {
+        let mut body = chunked("3\r\nabc\r\n3\r\ndef\r\n0\r\n\r\n");
+        body.limit(5);
+        assert_eq!(
+            body.read_to_vec().unwrap_err().kind(),
+            ErrorKind::FileTooLarge
+        );
+
+        let mut body = chunked("3\r\nabc\r\n3\r\ndef\r\n0\r\n\r\n");
+        body.limit(6);
+        assert_eq!(body.read_to_vec().unwrap(), b"abcdef");
+    }
+
+    #[test]
+    fn sends_continue_on_the_first_read_only() {
+        let sink = Sink::default();
+        let mut body = Body::from_bytes(b"abc".to_vec());
+        body.continue_on_read(Box::new(sink.clone()));
+        assert!(sink.0.lock().unwrap().is_empty());
+
+        let mut buf = [0; 2];
+        assert_eq!(body.read(&mut buf).unwrap(), 2);
+        assert_eq!(*sink.0.lock().unwrap(), b"HTTP/1.1 100 Continue\r\n\r\n");
+        assert_eq!(body.read_to_vec().unwrap(), b"c");
+        assert_eq!(*sink.0.lock().unwrap(), b"HTTP/1.1 100 Continue\r\n\r\n");
+
+        // Nothing to wait for, so no interim response either
+        let sink = Sink::default();
+        let mut body = Body::empty();
+        body.continue_on_read(Box::new(sink.clone()));
+        assert!(body.read_to_vec().unwrap().is_empty());
+        assert!(sink.0.lock().unwrap().is_empty());
+    }
+}
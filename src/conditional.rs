@@ -0,0 +1,263 @@
+use crate::date::{parse_http_date, truncate_to_secs};
+use crate::http::{Header, RequestMethod};
+use crate::request::Request;
+use std::time::SystemTime;
+
+// The validators of the selected representation, or of nothing when the
+// target resource does not exist.
+#[derive(Clone, Debug, Default)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<SystemTime>,
+}
+
+impl Validators {
+    pub fn none() -> Self {
+        Validators::default()
+    }
+
+    fn exists(&self) -> bool {
+        self.etag.is_some() || self.last_modified.is_some()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Precondition {
+    Proceed,
+    NotModified,
+    Failed,
+}
+
+// Evaluates If-Match, If-Unmodified-Since, If-None-Match and If-Modified-Since
+// in the order RFC 9110 section 13.2.2 prescribes.
+pub fn evaluate(req: &Request, validators: &Validators) -> Precondition {
+    let safe = matches!(req.method, RequestMethod::GET | RequestMethod::HEAD);
+
+    if let Some(if_match) = req.headers.get(&Header::IfMatch) {
+        if !matches_any(if_match, validators, strong_compare) {
+            return Precondition::Failed;
+        }
+    } else if let Some(if_unmodified_since) = req.headers.get(&Header::IfUnmodifiedSince) {
+        if let Some(since) = parse_http_date(if_unmodified_since) {
+            match validators.last_modified.map(truncate_to_secs) {
+                Some(modified) if modified <= since => {}
+                _ => return Precondition::Failed,
+            }
+        }
+    }
+
+    if let Some(if_none_match) = req.headers.get(&Header::IfNoneMatch) {
+        if matches_any(if_none_match, validators, weak_compare) {
+            return if safe {
+                Precondition::NotModified
+            } else {
+                Precondition::Failed
+            };
+        }
+    } else if let Some(if_modified_since) = req.headers.get(&Header::IfModifiedSince) {
+        if safe {
+            let since = parse_http_date(if_modified_since);
+            let modified = validators.last_modified.map(truncate_to_secs);
+            if let (Some(since), Some(modified)) = (since, modified) {
+                if modified <= since {
+                    return Precondition::NotModified;
+                }
+            }
+        }
+    }
+
+    Precondition::Proceed
+}
+
+// True if `If-Range` allows serving a partial response
+pub fn if_range_matches(if_range: &str, validators: &Validators) -> bool {
+    let if_range = if_range.trim();
+    if if_range.starts_with('"') || if_range.starts_with("W/") {
+        match &validators.etag {
+            Some(etag) => strong_compare(if_range, etag),
+            None => false,
+        }
+    } else {
+        let modified = validators.last_modified.map(truncate_to_secs);
+        modified.is_some() && parse_http_date(if_range) == modified
+    }
+}
+
+fn matches_any(list: &str, validators: &Validators, compare: fn(&str, &str) -> bool) -> bool {
+    if list.trim() == "*" {
+        return validators.exists();
+    }
+    match &validators.etag {
+        Some(etag) => split_etags(list).any(|candidate| compare(candidate, etag)),
+        None => false,
+    }
+}
+
+// Splits a comma-separated list of entity tags, keeping commas inside quotes
+fn split_etags(list: &str) -> impl Iterator<Item = &str> {
+    let mut tags = Vec::new();
+    let mut in_quotes = false;
+    let mut start = 0;
+    for (i, c) in list.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => {
+                tags.push(list[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    tags.push(list[start..].trim());
+    tags.into_iter().filter(|tag| !tag.is_empty())
+}
+
+fn strong_compare(a: &str, b: &str) -> bool {
+    !a.starts_with("W/") && !b.starts_with("W/") && a == b
+}
+
+fn weak_compare(a: &str, b: &str) -> bool {
+    a.trim_start_matches("W/") == b.trim_start_matches("W/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Body;
+    use crate::date::format_http_date;
+    use std::collections::HashMap;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn request(method: RequestMethod, headers: &[(Header, &str)]) -> Request {
+        Request {
+            method,
+            target: "/files/a".to_string(),
+            version: "HTTP/1.1".to_string(),
+            body: Body::empty(),
+            headers: headers
+                .iter()
+                .map(|(header, value)| (header.clone(), value.to_string()))
+                .collect::<HashMap<_, _>>(),
+            params: Vec::new(),
+        }
+    }
+
+    fn modified() -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(1_700_000_000_500)
+    }
+
+    fn validators(etag: &str) -> Validators {
+        Validators {
+            etag: Some(etag.to_string()),
+            last_modified: Some(modified()),
+        }
+    }
+
+    fn evaluate_get(headers: &[(Header, &str)], validators: &Validators) -> Precondition {
+        evaluate(&request(RequestMethod::GET, headers), validators)
+    }
+
+    fn evaluate_put(headers: &[(Header, &str)], validators: &Validators) -> Precondition {
+        evaluate(&request(RequestMethod::PUT, headers), validators)
+    }
+
+    #[test]
+    fn if_match_compares_strongly() {
+        let strong = validators("\"v1\"");
+        let weak = validators("W/\"v1\"");
+        assert_eq!(evaluate_put(&[(Header::IfMatch, "\"v1\"")], &strong), Precondition::Proceed);
+        assert_eq!(
+            evaluate_put(&[(Header::IfMatch, "\"v0\", \"v1\"")], &strong),
+            Precondition::Proceed
+        );
+        assert_eq!(evaluate_put(&[(Header::IfMatch, "W/\"v1\"")], &strong), Precondition::Failed);
+        assert_eq!(evaluate_put(&[(Header::IfMatch, "\"v1\"")], &weak), Precondition::Failed);
+        assert_eq!(evaluate_put(&[(Header::IfMatch, "*")], &strong), Precondition::Proceed);
+        assert_eq!(
+            evaluate_put(&[(Header::IfMatch, "*")], &Validators::none()),
+            Precondition::Failed
+        );
+    }
+
+    #[test]
+    fn if_none_match_compares_weakly() {
+        let weak = validators("W/\"v1\"");
+        assert_eq!(
+            evaluate_get(&[(Header::IfNoneMatch, "\"v1\"")], &weak),
+            Precondition::NotModified
+        );
+        assert_eq!(
+            evaluate_get(&[(Header::IfNoneMatch, "\"a,b\", W/\"v1\"")], &weak),
+            Precondition::NotModified
+        );
+        assert_eq!(evaluate_get(&[(Header::IfNoneMatch, "\"v2\"")], &weak), Precondition::Proceed);
+        // Unsafe methods fail instead of getting 304
+        assert_eq!(evaluate_put(&[(Header::IfNoneMatch, "\"v1\"")], &weak), Precondition::Failed);
+        assert_eq!(evaluate_put(&[(Header::IfNoneMatch, "*")], &weak), Precondition::Failed);
+        assert_eq!(
+            evaluate_put(&[(Header::IfNoneMatch, "*")], &Validators::none()),
+            Precondition::Proceed
+        );
+    }
+
+    #[test]
+    fn dates_compare_to_the_second() {
+        let validators = validators("\"v1\"");
+        let same = format_http_date(modified());
+        let earlier = format_http_date(modified() - Duration::from_secs(1));
+        assert_eq!(
+            evaluate_get(&[(Header::IfModifiedSince, &same)], &validators),
+            Precondition::NotModified
+        );
+        assert_eq!(
+            evaluate_get(&[(Header::IfModifiedSince, &earlier)], &validators),
+            Precondition::Proceed
+        );
+        assert_eq!(
+            evaluate_put(&[(Header::IfUnmodifiedSince, &same)], &validators),
+            Precondition::Proceed
+        );
+        assert_eq!(
+            evaluate_put(&[(Header::IfUnmodifiedSince, &earlier)], &validators),
+            Precondition::Failed
+        );
+        // An unparseable date is ignored
+        assert_eq!(
+            evaluate_put(&[(Header::IfUnmodifiedSince, "yesterday")], &validators),
+            Precondition::Proceed
+        );
+    }
+
+    #[test]
+    fn etags_take_precedence_over_dates() {
+        let validators = validators("\"v1\"");
+        let earlier = format_http_date(modified() - Duration::from_secs(1));
+        let same = format_http_date(modified());
+        assert_eq!(
+            evaluate_put(
+                &[(Header::IfMatch, "\"v1\""), (Header::IfUnmodifiedSince, &earlier)],
+                &validators
+            ),
+            Precondition::Proceed
+        );
+        assert_eq!(
+            evaluate_get(
+                &[(Header::IfNoneMatch, "\"v2\""), (Header::IfModifiedSince, &same)],
+                &validators
+            ),
+            Precondition::Proceed
+        );
+    }
+
+    #[test]
+    fn if_range() {
+        let strong = validators("\"v1\"");
+        assert!(if_range_matches("\"v1\"", &strong));
+        assert!(!if_range_matches("\"v2\"", &strong));
+        assert!(!if_range_matches("W/\"v1\"", &validators("W/\"v1\"")));
+        assert!(if_range_matches(&format_http_date(modified()), &strong));
+        let later = format_http_date(modified() + Duration::from_secs(1));
+        assert!(!if_range_matches(&later, &strong));
+        assert!(!if_range_matches("\"v1\"", &Validators::none()));
+    }
+}
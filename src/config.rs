@@ -0,0 +1,106 @@
+use crate::sandbox::{Sandbox, SymlinkPolicy};
+use std::path::{Path, PathBuf};
+
+pub struct Config {
+    pub directory: Option<PathBuf>,
+    pub symlinks: SymlinkPolicy,
+    pub files: Option<Sandbox>,
+    // Serve an HTML/JSON listing for directories under /files
+    pub listings: bool,
+    // File served instead of a listing when a directory contains it
+    pub index_file: Option<String>,
+    // Requests declaring a larger body are refused with 413 before it is read
+    pub max_body_size: Option<u64>,
+    // POST /files stores blobs under their sha-256 digest instead of a name
+    pub content_addressed: bool,
+    // Directory of custom pages for error statuses, e.g. `404.html`
+    pub error_pages: Option<PathBuf>,
+    // Host patterns served from a directory of their own, from
+    // `--vhost <pattern>=<directory>`
+    pub virtual_hosts: Vec<(String, PathBuf)>,
+    // Log every request to stderr, from `--log-requests`
+    pub log_requests: bool,
+}
+
+impl Config {
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> Config {
+        let mut directory = None;
+        let mut symlinks = SymlinkPolicy::FollowWithinRoot;
+        let mut listings = false;
+        let mut index_file = None;
+        let mut max_body_size = None;
+        let mut content_addressed = false;
+        let mut error_pages = None;
+        let mut virtual_hosts = Vec::new();
+        let mut log_requests = false;
+
+        let mut args = args.into_iter().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--directory" => directory = args.next().map(PathBuf::from),
+                "--symlinks" => match args.next().as_deref().map(SymlinkPolicy::from_string) {
+                    Some(Ok(policy)) => symlinks = policy,
+                    Some(Err(e)) => eprintln!("Error: {}", e),
+                    None => eprintln!("Error: --symlinks expects a value"),
+                },
+                "--listings" => listings = true,
+                "--index" => index_file = args.next(),
+                "--max-body-size" => match args.next().map(|size| size.parse::<u64>()) {
+                    Some(Ok(size)) => max_body_size = Some(size),
+                    _ => eprintln!("Error: --max-body-size expects a number of bytes"),
+                },
+                "--cas" => content_addressed = true,
+                "--error-pages" => error_pages = args.next().map(PathBuf::from),
+                "--vhost" => match args.next().as_deref().and_then(|arg| arg.split_once('=')) {
+                    Some((pattern, dir)) => virtual_hosts.push((pattern.to_string(), dir.into())),
+                    None => eprintln!("Error: --vhost expects <pattern>=<directory>"),
+                },
+                "--log-requests" => log_requests = true,
+                _ => {}
+            }
+        }
+
+        let files = directory
+            .as_ref()
+            .and_then(|directory| open_files(directory, symlinks));
+
+        Config {
+            directory,
+            symlinks,
+            files,
+            listings,
+            index_file,
+            max_body_size,
+            content_addressed,
+            error_pages,
+            virtual_hosts,
+            log_requests,
+        }
+    }
+
+    // The same settings for a virtual host serving files from `directory`
+    pub fn with_directory(&self, directory: PathBuf) -> Config {
+        Config {
+            files: open_files(&directory, self.symlinks),
+            directory: Some(directory),
+            symlinks: self.symlinks,
+            listings: self.listings,
+            index_file: self.index_file.clone(),
+            max_body_size: self.max_body_size,
+            content_addressed: self.content_addressed,
+            error_pages: self.error_pages.clone(),
+            virtual_hosts: Vec::new(),
+            log_requests: self.log_requests,
+        }
+    }
+}
+
+fn open_files(directory: &Path, symlinks: SymlinkPolicy) -> Option<Sandbox> {
+    match Sandbox::new(directory, symlinks) {
+        Ok(sandbox) => Some(sandbox),
+        Err(e) => {
+            eprintln!("Error: cannot serve {}: {}", directory.display(), e);
+            None
+        }
+    }
+}
@@ -0,0 +1,561 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+
+// Hash algorithms from the HTTP Digest Algorithm Values registry that we
+// implement, in order of preference.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DigestAlgorithm {
+    Sha512,
+    Sha256,
+}
+
+impl DigestAlgorithm {
+    pub fn from_string(algorithm: &str) -> Option<Self> {
+        match algorithm.trim().to_lowercase().as_str() {
+            "sha-256" => Some(DigestAlgorithm::Sha256),
+            "sha-512" => Some(DigestAlgorithm::Sha512),
+            _ => None,
+        }
+    }
+
+    pub fn hasher(self) -> Hasher {
+        match self {
+            DigestAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            DigestAlgorithm::Sha512 => Hasher::Sha512(Sha512::new()),
+        }
+    }
+
+    pub fn digest(self, data: &[u8]) -> Vec<u8> {
+        let mut hasher = self.hasher();
+        hasher.update(data);
+        hasher.finish()
+    }
+
+    pub fn digest_reader<R: Read>(self, reader: R) -> io::Result<Vec<u8>> {
+        let mut reader = HashingReader::new(reader, &[self]);
+        io::copy(&mut reader, &mut io::sink())?;
+        Ok(reader.digests().remove(0).1)
+    }
+}
+
+impl fmt::Display for DigestAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DigestAlgorithm::Sha256 => write!(f, "sha-256"),
+            DigestAlgorithm::Sha512 => write!(f, "sha-512"),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum Hasher {
+    Sha256(Sha256),
+    Sha512(Sha512),
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Sha512(hasher) => hasher.update(data),
+        }
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        match self {
+            Hasher::Sha256(hasher) => hasher.finish().to_vec(),
+            Hasher::Sha512(hasher) => hasher.finish().to_vec(),
+        }
+    }
+}
+
+// Hashes everything written to it, such as a body as it would be sent
+impl Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Hashes everything read through it with each of `hashers`
+pub struct HashingReader<R: Read> {
+    inner: R,
+    hashers: Vec<(DigestAlgorithm, Hasher)>,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub fn new(inner: R, algorithms: &[DigestAlgorithm]) -> Self {
+        HashingReader {
+            inner,
+            hashers: algorithms
+                .iter()
+                .map(|algorithm| (*algorithm, algorithm.hasher()))
+                .collect(),
+        }
+    }
+
+    // The digests of everything read so far
+    pub fn digests(&self) -> Vec<(DigestAlgorithm, Vec<u8>)> {
+        self.hashers
+            .iter()
+            .map(|(algorithm, hasher)| (*algorithm, hasher.clone().finish()))
+            .collect()
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        for (_, hasher) in &mut self.hashers {
+            hasher.update(&buf[..read]);
+        }
+        Ok(read)
+    }
+}
+
+// Parses a Content-Digest / Repr-Digest dictionary such as
+// `sha-256=:X48E9q...=:, sha-512=:...:`. Unknown algorithms are skipped;
+// malformed members fail the whole field.
+pub fn parse_digest_field(value: &str) -> Option<Vec<(DigestAlgorithm, Vec<u8>)>> {
+    let mut digests = Vec::new();
+    for member in value.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+        let (algorithm, digest) = member.split_once('=')?;
+        let digest = digest.trim().strip_prefix(':')?.strip_suffix(':')?;
+        let digest = base64_decode(digest)?;
+        if let Some(algorithm) = DigestAlgorithm::from_string(algorithm) {
+            digests.push((algorithm, digest));
+        }
+    }
+    Some(digests)
+}
+
+pub fn format_digest_field(digests: &[(DigestAlgorithm, Vec<u8>)]) -> String {
+    digests
+        .iter()
+        .map(|(algorithm, digest)| format!("{}=:{}:", algorithm, base64_encode(digest)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// Picks the algorithm a `Want-Content-Digest` / `Want-Repr-Digest` value
+// prefers most (e.g. `sha-256=3, sha-512=10`). None means the client asked
+// for no digest at all; unsupported-only preferences fall back to sha-256.
+pub fn preferred_algorithm(want: &str) -> Option<DigestAlgorithm> {
+    let mut best: Option<(u32, DigestAlgorithm)> = None;
+    let mut any_supported = false;
+    for member in want.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+        let (algorithm, preference) = member.split_once('=').unwrap_or((member, "1"));
+        let algorithm = match DigestAlgorithm::from_string(algorithm) {
+            Some(algorithm) => algorithm,
+            None => continue,
+        };
+        any_supported = true;
+        let preference: u32 = preference.trim().parse().unwrap_or(0);
+        if preference > 0 && best.map(|(p, _)| preference > p).unwrap_or(true) {
+            best = Some((preference, algorithm));
+        }
+    }
+    match best {
+        Some((_, algorithm)) => Some(algorithm),
+        None if any_supported => None,
+        None => Some(DigestAlgorithm::Sha256),
+    }
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn base64_encode(input: &[u8]) -> String {
+    let mut encoded = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        encoded.push(BASE64[(n >> 18) as usize & 63] as char);
+        encoded.push(BASE64[(n >> 12) as usize & 63] as char);
+        if chunk.len() > 1 {
+            encoded.push(BASE64[(n >> 6) as usize & 63] as char);
+        } else {
+            encoded.push('=');
+        }
+        if chunk.len() > 2 {
+            encoded.push(BASE64[n as usize & 63] as char);
+        } else {
+            encoded.push('=');
+        }
+    }
+    encoded
+}
+
+pub fn base64_decode(input: &str) -> Option<Vec<u8>> {
+    let input = input.trim_end_matches('=');
+    // A single character left over can't encode a whole byte
+    if input.len() % 4 == 1 {
+        return None;
+    }
+    let mut decoded = Vec::with_capacity(input.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in input.bytes() {
+        let value = BASE64.iter().position(|&b| b == c)? as u32;
+        buffer = buffer << 6 | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(decoded)
+}
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+#[derive(Clone)]
+pub struct Sha256 {
+    state: [u32; 8],
+    buffer: Vec<u8>,
+    length: u64,
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Sha256::new()
+    }
+}
+
+impl Sha256 {
+    pub fn new() -> Self {
+        Sha256 {
+            state: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+                0x5be0cd19,
+            ],
+            buffer: Vec::with_capacity(64),
+            length: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u64;
+        if !self.buffer.is_empty() {
+            let take = (64 - self.buffer.len()).min(data.len());
+            self.buffer.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.buffer.len() < 64 {
+                return;
+            }
+            let block: [u8; 64] = self.buffer[..].try_into().unwrap();
+            self.compress(&block);
+            self.buffer.clear();
+        }
+        let mut blocks = data.chunks_exact(64);
+        for block in &mut blocks {
+            self.compress(block.try_into().unwrap());
+        }
+        self.buffer.extend_from_slice(blocks.remainder());
+    }
+
+    pub fn finish(mut self) -> [u8; 32] {
+        let bit_length = self.length.wrapping_mul(8);
+        let mut padding = vec![0x80u8];
+        padding.resize((119 - (self.length % 64) as usize) % 64 + 1, 0);
+        padding.extend_from_slice(&bit_length.to_be_bytes());
+        self.update(&padding);
+
+        let mut digest = [0u8; 32];
+        for (chunk, word) in digest.chunks_exact_mut(4).zip(self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self, block: &[u8; 64]) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(SHA256_K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
+
+const SHA512_K: [u64; 80] = [
+    0x428a2f98d728ae22, 0x7137449123ef65cd, 0xb5c0fbcfec4d3b2f, 0xe9b5dba58189dbbc,
+    0x3956c25bf348b538, 0x59f111f1b605d019, 0x923f82a4af194f9b, 0xab1c5ed5da6d8118,
+    0xd807aa98a3030242, 0x12835b0145706fbe, 0x243185be4ee4b28c, 0x550c7dc3d5ffb4e2,
+    0x72be5d74f27b896f, 0x80deb1fe3b1696b1, 0x9bdc06a725c71235, 0xc19bf174cf692694,
+    0xe49b69c19ef14ad2, 0xefbe4786384f25e3, 0x0fc19dc68b8cd5b5, 0x240ca1cc77ac9c65,
+    0x2de92c6f592b0275, 0x4a7484aa6ea6e483, 0x5cb0a9dcbd41fbd4, 0x76f988da831153b5,
+    0x983e5152ee66dfab, 0xa831c66d2db43210, 0xb00327c898fb213f, 0xbf597fc7beef0ee4,
+    0xc6e00bf33da88fc2, 0xd5a79147930aa725, 0x06ca6351e003826f, 0x142929670a0e6e70,
+    0x27b70a8546d22ffc, 0x2e1b21385c26c926, 0x4d2c6dfc5ac42aed, 0x53380d139d95b3df,
+    0x650a73548baf63de, 0x766a0abb3c77b2a8, 0x81c2c92e47edaee6, 0x92722c851482353b,
+    0xa2bfe8a14cf10364, 0xa81a664bbc423001, 0xc24b8b70d0f89791, 0xc76c51a30654be30,
+    0xd192e819d6ef5218, 0xd69906245565a910, 0xf40e35855771202a, 0x106aa07032bbd1b8,
+    0x19a4c116b8d2d0c8, 0x1e376c085141ab53, 0x2748774cdf8eeb99, 0x34b0bcb5e19b48a8,
+    0x391c0cb3c5c95a63, 0x4ed8aa4ae3418acb, 0x5b9cca4f7763e373, 0x682e6ff3d6b2b8a3,
+    0x748f82ee5defb2fc, 0x78a5636f43172f60, 0x84c87814a1f0ab72, 0x8cc702081a6439ec,
+    0x90befffa23631e28, 0xa4506cebde82bde9, 0xbef9a3f7b2c67915, 0xc67178f2e372532b,
+    0xca273eceea26619c, 0xd186b8c721c0c207, 0xeada7dd6cde0eb1e, 0xf57d4f7fee6ed178,
+    0x06f067aa72176fba, 0x0a637dc5a2c898a6, 0x113f9804bef90dae, 0x1b710b35131c471b,
+    0x28db77f523047d84, 0x32caab7b40c72493, 0x3c9ebe0a15c9bebc, 0x431d67c49c100d4c,
+    0x4cc5d4becb3e42b6, 0x597f299cfc657e2a, 0x5fcb6fab3ad6faec, 0x6c44198c4a475817,
+];
+
+#[derive(Clone)]
+pub struct Sha512 {
+    state: [u64; 8],
+    buffer: Vec<u8>,
+    length: u128,
+}
+
+impl Default for Sha512 {
+    fn default() -> Self {
+        Sha512::new()
+    }
+}
+
+impl Sha512 {
+    pub fn new() -> Self {
+        Sha512 {
+            state: [
+                0x6a09e667f3bcc908,
+                0xbb67ae8584caa73b,
+                0x3c6ef372fe94f82b,
+                0xa54ff53a5f1d36f1,
+                0x510e527fade682d1,
+                0x9b05688c2b3e6c1f,
+                0x1f83d9abfb41bd6b,
+                0x5be0cd19137e2179,
+            ],
+            buffer: Vec::with_capacity(128),
+            length: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u128;
+        if !self.buffer.is_empty() {
+            let take = (128 - self.buffer.len()).min(data.len());
+            self.buffer.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.buffer.len() < 128 {
+                return;
+            }
+            let block: [u8; 128] = self.buffer[..].try_into().unwrap();
+            self.compress(&block);
+            self.buffer.clear();
+        }
+        let mut blocks = data.chunks_exact(128);
+        for block in &mut blocks {
+            self.compress(block.try_into().unwrap());
+        }
+        self.buffer.extend_from_slice(blocks.remainder());
+    }
+
+    pub fn finish(mut self) -> [u8; 64] {
+        let bit_length = self.length.wrapping_mul(8);
+        let mut padding = vec![0x80u8];
+        padding.resize((239 - (self.length % 128) as usize) % 128 + 1, 0);
+        padding.extend_from_slice(&bit_length.to_be_bytes());
+        self.update(&padding);
+
+        let mut digest = [0u8; 64];
+        for (chunk, word) in digest.chunks_exact_mut(8).zip(self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self, block: &[u8; 128]) {
+        let mut w = [0u64; 80];
+        for (i, word) in block.chunks_exact(8).enumerate() {
+            w[i] = u64::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..80 {
+            let s0 = w[i - 15].rotate_right(1) ^ w[i - 15].rotate_right(8) ^ (w[i - 15] >> 7);
+            let s1 = w[i - 2].rotate_right(19) ^ w[i - 2].rotate_right(61) ^ (w[i - 2] >> 6);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..80 {
+            let s1 = e.rotate_right(14) ^ e.rotate_right(18) ^ e.rotate_right(41);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(SHA512_K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(28) ^ a.rotate_right(34) ^ a.rotate_right(39);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex_digest(algorithm: DigestAlgorithm, data: &[u8]) -> String {
+        hex::encode(algorithm.digest(data))
+    }
+
+    #[test]
+    fn sha256_known_answers() {
+        assert_eq!(
+            hex_digest(DigestAlgorithm::Sha256, b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex_digest(DigestAlgorithm::Sha256, b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        // Two blocks once padded
+        assert_eq!(
+            hex_digest(
+                DigestAlgorithm::Sha256,
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            ),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[test]
+    fn sha512_known_answers() {
+        assert_eq!(
+            hex_digest(DigestAlgorithm::Sha512, b""),
+            concat!(
+                "cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce",
+                "47d0d13c5d85f2b0ff8318d2877eec2f63b931bd47417a81a538327af927da3e"
+            )
+        );
+        assert_eq!(
+            hex_digest(DigestAlgorithm::Sha512, b"abc"),
+            concat!(
+                "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a",
+                "2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"
+            )
+        );
+    }
+
+    #[test]
+    fn mebibyte_input_hashed_in_pieces() {
+        let data: Vec<u8> = (0..1 << 20).map(|i| (i % 251) as u8).collect();
+        let sha256 = "631b84027d6b9e52b539c4e8373622d23032dfadc64d60af87339c9037e4f769";
+        let sha512 = concat!(
+            "67dad569eefc986a3b2424f5516d5a0284bb53d7b52d75f5ed881a6830a95765",
+            "ccc82bc48752fb693422579f11dc9a400561ec1885af9eeef703dbbd312d4fd0"
+        );
+        assert_eq!(hex_digest(DigestAlgorithm::Sha256, &data), sha256);
+        assert_eq!(hex_digest(DigestAlgorithm::Sha512, &data), sha512);
+
+        // Odd-sized reads cross block boundaries
+        let reader = io::Cursor::new(&data).chain(io::empty());
+        let mut reader = HashingReader::new(
+            reader,
+            &[DigestAlgorithm::Sha256, DigestAlgorithm::Sha512],
+        );
+        let mut buffer = [0; 1000];
+        while reader.read(&mut buffer[..777]).unwrap() > 0 {}
+        let digests = reader.digests();
+        assert_eq!(hex::encode(&digests[0].1), sha256);
+        assert_eq!(hex::encode(&digests[1].1), sha512);
+    }
+
+    #[test]
+    fn base64_padding() {
+        let cases = [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ];
+        for (plain, encoded) in cases {
+            assert_eq!(base64_encode(plain.as_bytes()), encoded);
+            assert_eq!(base64_decode(encoded).unwrap(), plain.as_bytes());
+        }
+        assert_eq!(base64_encode(&[0xfb, 0xff]), "+/8=");
+        assert_eq!(base64_decode("Z"), None);
+        assert_eq!(base64_decode("Zm9v!"), None);
+    }
+
+    #[test]
+    fn digest_fields() {
+        let field = "sha-256=:ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0=:";
+        let digests = parse_digest_field(field).unwrap();
+        let abc = DigestAlgorithm::Sha256.digest(b"abc");
+        assert_eq!(digests, vec![(DigestAlgorithm::Sha256, abc)]);
+        assert_eq!(format_digest_field(&digests), field);
+        assert_eq!(parse_digest_field("sha-256=abc"), None);
+        assert_eq!(parse_digest_field("md5=:AA==:"), Some(Vec::new()));
+    }
+
+    #[test]
+    fn preferred_algorithms() {
+        assert_eq!(preferred_algorithm("sha-256=3, sha-512=10"), Some(DigestAlgorithm::Sha512));
+        assert_eq!(preferred_algorithm("sha-256=0"), None);
+        assert_eq!(preferred_algorithm("md5=1"), Some(DigestAlgorithm::Sha256));
+    }
+}
@@ -3,9 +3,10 @@ extern crate flate2;
 use flate2::read::GzDecoder;
 use flate2::write::GzEncoder;
 use flate2::Compression;
-use hex::encode;
 use std::io::{Read, Write};
 
+// Named after the Content-Encoding tokens
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Eq, PartialEq, Hash)]
 pub enum ContentEncoding {
     NONE,
@@ -15,7 +16,7 @@ pub enum ContentEncoding {
 pub trait Encoding {
     fn from_string(encoding: &str) -> ContentEncoding;
     fn to_string(encoding: &ContentEncoding) -> Option<String>;
-    fn encode(&self, input: &str) -> Vec<u8>;
+    fn encode(&self, input: &[u8]) -> Vec<u8>;
     fn decode(&self, input: &[u8]) -> String;
 }
 
@@ -35,12 +36,12 @@ impl Encoding for ContentEncoding {
         }
     }
 
-    fn encode(&self, input: &str) -> Vec<u8> {
+    fn encode(&self, input: &[u8]) -> Vec<u8> {
         match self {
-            ContentEncoding::NONE => input.as_bytes().to_vec(),
+            ContentEncoding::NONE => input.to_vec(),
             ContentEncoding::GZIP => {
                 let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
-                encoder.write_all(input.as_bytes()).unwrap();
+                encoder.write_all(input).unwrap();
                 encoder.finish().unwrap()
             }
         }
@@ -58,3 +59,32 @@ impl Encoding for ContentEncoding {
         }
     }
 }
+
+// Parses an Accept-Encoding value into (coding, qvalue) pairs, lowercased.
+pub fn parse_accept_encoding(accept_encoding: &str) -> Vec<(String, f32)> {
+    accept_encoding
+        .split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|item| {
+            let mut params = item.split(';').map(|s| s.trim());
+            let coding = params.next().unwrap_or_default().to_lowercase();
+            let quality = params
+                .filter_map(|param| param.strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            (coding, quality)
+        })
+        .collect()
+}
+
+// Returns the qvalue the client assigned to `coding`, falling back to `*`.
+pub fn accepted_quality(accept_encoding: &str, coding: &str) -> f32 {
+    let codings = parse_accept_encoding(accept_encoding);
+    codings
+        .iter()
+        .find(|(name, _)| name == coding)
+        .or_else(|| codings.iter().find(|(name, _)| name == "*"))
+        .map(|(_, quality)| *quality)
+        .unwrap_or(0.0)
+}
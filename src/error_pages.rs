@@ -0,0 +1,70 @@
+use crate::http::{ContentType, Header};
+use crate::response::Response;
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+// Bodies sent in place of those of error responses, read once from files
+// named after the status, e.g. `404.html` and `404.json`
+#[derive(Default)]
+pub struct ErrorPages {
+    html: HashMap<u16, Vec<u8>>,
+    json: HashMap<u16, Vec<u8>>,
+}
+
+impl ErrorPages {
+    pub fn load(dir: &Path) -> io::Result<ErrorPages> {
+        let mut pages = ErrorPages::default();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let (stem, extension) = match (path.file_stem(), path.extension()) {
+                (Some(stem), Some(extension)) => (stem.to_string_lossy(), extension),
+                _ => continue,
+            };
+            let status = match stem.parse::<u16>() {
+                Ok(status) if (400..600).contains(&status) => status,
+                _ => continue,
+            };
+            match extension.to_str() {
+                Some("html") => pages.html.insert(status, std::fs::read(&path)?),
+                Some("json") => pages.json.insert(status, std::fs::read(&path)?),
+                _ => continue,
+            };
+        }
+        Ok(pages)
+    }
+
+    // Replaces the body of an error response with the page for its status.
+    // JSON is sent to clients that accept it, HTML to others, and either one
+    // when it is the only page there is.
+    pub fn apply(&self, response: &mut Response, accept: Option<&str>) {
+        let status = response.status.code.to_u16();
+        if status < 400 {
+            return;
+        }
+        let wants_json = accept
+            .map(|accept| accept.contains("application/json"))
+            .unwrap_or(false);
+        let html = self
+            .html
+            .get(&status)
+            .map(|page| (page, format!("{}; charset=utf-8", ContentType::TextHtml)));
+        let json = self
+            .json
+            .get(&status)
+            .map(|page| (page, ContentType::ApplicationJson.to_string()));
+        let page = if wants_json {
+            json.or(html)
+        } else {
+            html.or(json)
+        };
+
+        if let Some((body, content_type)) = page {
+            response.body = body.clone();
+            response.stream = None;
+            response.headers.remove(&Header::ContentEncoding);
+            response.headers.insert(Header::ContentType, content_type);
+            response.headers.insert(Header::Vary, "Accept".to_string());
+        }
+    }
+}
@@ -0,0 +1,328 @@
+use crate::body;
+use crate::form::FormData;
+use crate::http::{self, RequestMethod, Status, StatusCode};
+use crate::json::{FromJson, JsonValue};
+use crate::request::{client_error, Request};
+use crate::response::{IntoResponse, Response};
+use crate::server::BoxFuture;
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::mem;
+use std::sync::Arc;
+use tokio::task;
+
+// A value a handler can take as an argument, built from the request before
+// the handler runs. An error is sent as the response instead.
+pub trait FromRequest<S>: Sized {
+    fn from_request(req: &mut Request, state: &S) -> Result<Self, Response>;
+}
+
+// A function taking the state and any number of extractors and returning
+// anything that converts into a response. `Args` only tells the
+// implementations for each number of arguments apart.
+//
+// Plain functions are given the state by reference and run on a blocking
+// thread, as they may do blocking I/O. Async functions are given it as an
+// `Arc` and awaited on the runtime, so waiting doesn't hold up a thread.
+pub trait Handler<S, Args>: Clone + Send + Sync + 'static {
+    fn call(&self, state: Arc<S>, req: Request) -> BoxFuture<'static, Result<Response, Response>>;
+}
+
+// Marks the arguments of an async handler
+pub struct Async<Args>(PhantomData<Args>);
+
+macro_rules! impl_handler {
+    ($($ty:ident $arg:ident),*) => {
+        impl<F, S, R, $($ty,)*> Handler<S, ($($ty,)*)> for F
+        where
+            F: Fn(&S, $($ty,)*) -> R + Clone + Send + Sync + 'static,
+            S: Send + Sync + 'static,
+            R: IntoResponse,
+            $($ty: FromRequest<S>,)*
+        {
+            #[allow(unused_mut, unused_variables)]
+            fn call(
+                &self,
+                state: Arc<S>,
+                mut req: Request,
+            ) -> BoxFuture<'static, Result<Response, Response>> {
+                let handler = self.clone();
+                Box::pin(blocking(move || {
+                    $(let $arg = $ty::from_request(&mut req, &state)?;)*
+                    Ok(handler(&state, $($arg,)*).into_response())
+                }))
+            }
+        }
+
+        impl<F, Fut, S, R, $($ty,)*> Handler<S, Async<($($ty,)*)>> for F
+        where
+            F: Fn(Arc<S>, $($ty,)*) -> Fut + Clone + Send + Sync + 'static,
+            Fut: Future<Output = R> + Send + 'static,
+            S: Send + Sync + 'static,
+            R: IntoResponse,
+            $($ty: FromRequest<S> + Send + 'static,)*
+        {
+            #[allow(unused_mut, unused_variables)]
+            fn call(
+                &self,
+                state: Arc<S>,
+                mut req: Request,
+            ) -> BoxFuture<'static, Result<Response, Response>> {
+                let handler = self.clone();
+                Box::pin(async move {
+                    // Extractors may read the body, which blocks
+                    let (state, ($($arg,)*)) = blocking(move || {
+                        $(let $arg = $ty::from_request(&mut req, &state)?;)*
+                        Ok((state, ($($arg,)*)))
+                    })
+                    .await?;
+                    Ok(handler(state, $($arg,)*).await.into_response())
+                })
+            }
+        }
+    };
+}
+
+impl_handler!();
+impl_handler!(T1 t1);
+impl_handler!(T1 t1, T2 t2);
+impl_handler!(T1 t1, T2 t2, T3 t3);
+impl_handler!(T1 t1, T2 t2, T3 t3, T4 t4);
+impl_handler!(T1 t1, T2 t2, T3 t3, T4 t4, T5 t5);
+impl_handler!(T1 t1, T2 t2, T3 t3, T4 t4, T5 t5, T6 t6);
+
+// Runs `f` on a blocking thread. A panic in it is answered with a 500.
+async fn blocking<T, F>(f: F) -> Result<T, Response>
+where
+    F: FnOnce() -> Result<T, Response> + Send + 'static,
+    T: Send + 'static,
+{
+    task::spawn_blocking(f).await.unwrap_or_else(|_| {
+        Err(Response::builder(
+            Status::new(StatusCode::InternalServerError),
+            "500 Internal Server Error".to_string(),
+            HashMap::new(),
+        ))
+    })
+}
+
+// The whole request. Extractors after it see an empty one, so it goes last.
+impl<S> FromRequest<S> for Request {
+    fn from_request(req: &mut Request, _state: &S) -> Result<Self, Response> {
+        let empty = Request {
+            method: RequestMethod::GET,
+            target: String::new(),
+            version: req.version.clone(),
+            body: body::Body::empty(),
+            headers: HashMap::new(),
+            params: Vec::new(),
+        };
+        Ok(mem::replace(req, empty))
+    }
+}
+
+// All request headers
+impl<S> FromRequest<S> for HashMap<http::Header, String> {
+    fn from_request(req: &mut Request, _state: &S) -> Result<Self, Response> {
+        Ok(req.headers.clone())
+    }
+}
+
+// An extractor that may fail without failing the request
+impl<S, T: FromRequest<S>> FromRequest<S> for Option<T> {
+    fn from_request(req: &mut Request, state: &S) -> Result<Self, Response> {
+        Ok(T::from_request(req, state).ok())
+    }
+}
+
+// The `{name}` segments of the matched route, e.g. `Path<String>` for
+// `/echo/{text}` or `Path<(String, u32)>` for `/users/{name}/posts/{id}`
+pub struct Path<T>(pub T);
+
+pub trait FromParams: Sized {
+    fn from_params(params: &[(String, String)]) -> Result<Self, String>;
+}
+
+impl<S, T: FromParams> FromRequest<S> for Path<T> {
+    fn from_request(req: &mut Request, _state: &S) -> Result<Self, Response> {
+        T::from_params(&req.params)
+            .map(Path)
+            .map_err(|e| client_error(StatusCode::BadRequest, format!("Invalid path: {}", e)))
+    }
+}
+
+impl FromParams for HashMap<String, String> {
+    fn from_params(params: &[(String, String)]) -> Result<Self, String> {
+        Ok(params.iter().cloned().collect())
+    }
+}
+
+fn parse_param<T: std::str::FromStr>((name, value): &(String, String)) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value for {}: {}", name, value))
+}
+
+fn expect_params(params: &[(String, String)], count: usize) -> Result<(), String> {
+    if params.len() != count {
+        return Err(format!(
+            "expected {} parameters, the route has {}",
+            count,
+            params.len()
+        ));
+    }
+    Ok(())
+}
+
+macro_rules! impl_from_params_value {
+    ($($ty:ty),*) => {
+        $(impl FromParams for $ty {
+            fn from_params(params: &[(String, String)]) -> Result<Self, String> {
+                expect_params(params, 1)?;
+                parse_param(&params[0])
+            }
+        })*
+    };
+}
+
+impl_from_params_value!(String, bool, i32, i64, u8, u16, u32, u64, usize);
+
+macro_rules! impl_from_params_tuple {
+    ($count:expr; $($ty:ident $index:tt),*) => {
+        impl<$($ty: std::str::FromStr,)*> FromParams for ($($ty,)*) {
+            fn from_params(params: &[(String, String)]) -> Result<Self, String> {
+                expect_params(params, $count)?;
+                Ok(($(parse_param::<$ty>(&params[$index])?,)*))
+            }
+        }
+    };
+}
+
+impl_from_params_tuple!(1; A 0);
+impl_from_params_tuple!(2; A 0, B 1);
+impl_from_params_tuple!(3; A 0, B 1, C 2);
+impl_from_params_tuple!(4; A 0, B 1, C 2, D 3);
+
+// The query string. A missing one is the same as an empty one.
+pub struct Query<T>(pub T);
+
+pub trait FromQuery: Sized {
+    fn from_query(query: &FormData) -> Result<Self, String>;
+}
+
+impl<S, T: FromQuery> FromRequest<S> for Query<T> {
+    fn from_request(req: &mut Request, _state: &S) -> Result<Self, Response> {
+        let query = FormData::parse(req.query().unwrap_or_default());
+        T::from_query(&query)
+            .map(Query)
+            .map_err(|e| client_error(StatusCode::BadRequest, format!("Invalid query: {}", e)))
+    }
+}
+
+impl FromQuery for FormData {
+    fn from_query(query: &FormData) -> Result<Self, String> {
+        Ok(query.clone())
+    }
+}
+
+// The first value of every key
+impl FromQuery for HashMap<String, String> {
+    fn from_query(query: &FormData) -> Result<Self, String> {
+        let mut map = HashMap::new();
+        for (key, value) in query.iter() {
+            map.entry(key.to_string())
+                .or_insert_with(|| value.to_string());
+        }
+        Ok(map)
+    }
+}
+
+// A header decoded into `T`. A missing or malformed header is a 400.
+pub struct Header<T>(pub T);
+
+pub trait TypedHeader: Sized {
+    fn name() -> http::Header;
+    fn decode(value: &str) -> Option<Self>;
+}
+
+impl<S, T: TypedHeader> FromRequest<S> for Header<T> {
+    fn from_request(req: &mut Request, _state: &S) -> Result<Self, Response> {
+        let name = T::name();
+        let value = req
+            .headers
+            .get(&name)
+            .ok_or_else(|| client_error(StatusCode::BadRequest, format!("Missing {}", name)))?;
+        T::decode(value)
+            .map(Header)
+            .ok_or_else(|| client_error(StatusCode::BadRequest, format!("Invalid {}", name)))
+    }
+}
+
+macro_rules! text_header {
+    ($name:ident) => {
+        pub struct $name(pub String);
+
+        impl TypedHeader for $name {
+            fn name() -> http::Header {
+                http::Header::$name
+            }
+
+            fn decode(value: &str) -> Option<Self> {
+                Some($name(value.to_string()))
+            }
+        }
+    };
+}
+
+text_header!(UserAgent);
+text_header!(Host);
+text_header!(Accept);
+
+// The whole body, buffered up to the limit for parsed bodies
+pub struct Body<T>(pub T);
+
+pub trait FromBody: Sized {
+    fn from_body(req: &mut Request) -> Result<Self, Response>;
+}
+
+impl<S, T: FromBody> FromRequest<S> for Body<T> {
+    fn from_request(req: &mut Request, _state: &S) -> Result<Self, Response> {
+        T::from_body(req).map(Body)
+    }
+}
+
+impl FromBody for Bytes {
+    fn from_body(req: &mut Request) -> Result<Self, Response> {
+        req.bytes().map(Bytes::from)
+    }
+}
+
+impl FromBody for Vec<u8> {
+    fn from_body(req: &mut Request) -> Result<Self, Response> {
+        req.bytes()
+    }
+}
+
+impl FromBody for String {
+    fn from_body(req: &mut Request) -> Result<Self, Response> {
+        req.text()
+    }
+}
+
+// A JSON body converted into `T`. Other content types are refused with 415.
+// Returned from a handler, it is sent as an application/json body.
+pub struct Json<T>(pub T);
+
+impl<S, T: FromJson> FromRequest<S> for Json<T> {
+    fn from_request(req: &mut Request, _state: &S) -> Result<Self, Response> {
+        req.json().map(Json)
+    }
+}
+
+impl<T: Into<JsonValue>> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        self.0.into().into_response()
+    }
+}
@@ -0,0 +1,473 @@
+use crate::conditional::Validators;
+use crate::digest::{DigestAlgorithm, HashingReader};
+use crate::encoding::{accepted_quality, ContentEncoding, Encoding};
+use crate::range::ByteRange;
+use crate::sandbox::Sandbox;
+use flate2::read::GzEncoder;
+use flate2::Compression;
+use std::cmp::Ordering;
+use std::fs::{File, Metadata, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::time::UNIX_EPOCH;
+
+// Precompressed siblings looked up next to a requested file, as
+// (content-coding, file extension), in order of preference on equal qvalues.
+const PRECOMPRESSED: [(&str, &str); 3] = [("br", "br"), ("zstd", "zst"), ("gzip", "gz")];
+
+pub struct Precompressed {
+    pub path: PathBuf,
+    pub coding: &'static str,
+}
+
+// Siblings are resolved through the sandbox like the file itself, so the
+// symlink policy applies to them too
+pub fn find_precompressed(
+    sandbox: &Sandbox,
+    path: &Path,
+    accept_encoding: &str,
+) -> Option<Precompressed> {
+    let dir = path.parent()?;
+    let name = path.file_name()?.to_string_lossy();
+    let mut candidates: Vec<(f32, Precompressed)> = PRECOMPRESSED
+        .iter()
+        .filter_map(|&(coding, extension)| {
+            let quality = accepted_quality(accept_encoding, coding);
+            if quality <= 0.0 {
+                return None;
+            }
+            let sibling = sandbox.join(dir, &format!("{}.{}", name, extension)).ok()?;
+            if sibling.is_file() {
+                Some((
+                    quality,
+                    Precompressed {
+                        path: sibling,
+                        coding,
+                    },
+                ))
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    // Stable sort, so ties keep the PRECOMPRESSED order
+    candidates.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+    candidates
+        .into_iter()
+        .next()
+        .map(|(_, precompressed)| precompressed)
+}
+
+// How a file is sent to a particular client
+pub enum Representation {
+    Precompressed(Precompressed),
+    Gzip,
+    Identity,
+}
+
+impl Representation {
+    pub fn coding(&self) -> Option<String> {
+        match self {
+            Representation::Precompressed(precompressed) => Some(precompressed.coding.to_string()),
+            Representation::Gzip => ContentEncoding::to_string(&ContentEncoding::GZIP),
+            Representation::Identity => None,
+        }
+    }
+}
+
+// Picks the best representation of `path` the client accepts: a precompressed
+// sibling when one exists, otherwise the file gzipped on the fly if gzip is
+// acceptable, otherwise the file as-is.
+pub fn negotiate(sandbox: &Sandbox, path: &Path, accept_encoding: &str) -> Representation {
+    if let Some(precompressed) = find_precompressed(sandbox, path, accept_encoding) {
+        return Representation::Precompressed(precompressed);
+    }
+    if accepted_quality(accept_encoding, "gzip") > 0.0 {
+        return Representation::Gzip;
+    }
+    Representation::Identity
+}
+
+// Opens the bytes sent for a representation, read as they go out, with their
+// length when it's known up front. A file gzipped on the fly has none.
+pub fn open_representation(
+    path: &Path,
+    representation: &Representation,
+) -> io::Result<(Box<dyn Read + Send>, Option<u64>)> {
+    if !path.is_file() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "Not a file"));
+    }
+    let path = match representation {
+        Representation::Precompressed(precompressed) => &precompressed.path,
+        Representation::Gzip => {
+            let encoder = GzEncoder::new(File::open(path)?, Compression::default());
+            return Ok((Box::new(encoder), None));
+        }
+        Representation::Identity => path,
+    };
+    let file = File::open(path)?;
+    let len = file.metadata()?.len();
+    Ok((Box::new(file), Some(len)))
+}
+
+// A strong entity tag derived from size and modification time. Encoded
+// representations get the coding appended, so they never match the identity one.
+pub fn etag(metadata: &Metadata, coding: Option<&str>) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|modified| modified.as_nanos())
+        .unwrap_or(0);
+    match coding {
+        Some(coding) => format!("\"{:x}-{:x}-{}\"", metadata.len(), modified, coding),
+        None => format!("\"{:x}-{:x}\"", metadata.len(), modified),
+    }
+}
+
+pub fn validators(metadata: &Metadata, coding: Option<&str>) -> Validators {
+    Validators {
+        etag: Some(etag(metadata, coding)),
+        last_modified: metadata.modified().ok(),
+    }
+}
+
+// Copies exactly `len` bytes from `reader`, failing if it runs out first, so
+// a file that shrank after its length was sent can't end a body early
+pub fn copy_exact(reader: impl Read, len: u64, out: &mut dyn Write) -> io::Result<()> {
+    let copied = io::copy(&mut reader.take(len), out)?;
+    if copied < len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "File shrank while reading",
+        ));
+    }
+    Ok(())
+}
+
+// Copies just the bytes covered by `range`, without reading the rest of the file
+pub fn copy_range(file: &mut File, range: &ByteRange, out: &mut dyn Write) -> io::Result<()> {
+    file.seek(SeekFrom::Start(range.start))?;
+    copy_exact(file, range.len(), out)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WriteMode {
+    // Fail with AlreadyExists if the destination exists
+    CreateNew,
+    // Atomically replace whatever is at the destination
+    Replace,
+}
+
+// Streams `body` into a temporary file next to `path`, fsyncs it and moves it
+// into place, so readers only ever see the old or the complete new content.
+// Returns the number of bytes written.
+pub fn write_atomic<R: Read>(path: &Path, body: &mut R, mode: WriteMode) -> io::Result<u64> {
+    write_atomic_checked(path, body, mode, |_| Ok(()))
+}
+
+// Like `write_atomic`, but lets `check` inspect the fully read body (e.g. to
+// verify a digest) and veto the write before anything becomes visible.
+pub fn write_atomic_checked<R, F>(
+    path: &Path,
+    body: &mut R,
+    mode: WriteMode,
+    check: F,
+) -> io::Result<u64>
+where
+    R: Read,
+    F: FnOnce(&mut R) -> io::Result<()>,
+{
+    let (staged, written) = stage(path, body, check)?;
+    staged.commit(mode)?;
+    Ok(written)
+}
+
+// A body written to a temporary file next to its destination but not yet
+// visible there. Dropping it without committing discards the body.
+pub struct Staged {
+    temp_path: PathBuf,
+    path: PathBuf,
+}
+
+impl Staged {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Moves the body into place
+    pub fn commit(self, mode: WriteMode) -> io::Result<()> {
+        match mode {
+            // A hard link fails if the destination exists, which makes
+            // create-only uploads race-free
+            WriteMode::CreateNew => std::fs::hard_link(&self.temp_path, &self.path)?,
+            WriteMode::Replace => std::fs::rename(&self.temp_path, &self.path)?,
+        }
+        match self.path.parent() {
+            Some(parent) => sync_dir(parent),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Staged {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.temp_path);
+    }
+}
+
+// Streams `body` into a temporary file next to `path` and fsyncs it, for
+// `commit` to move into place later. `check` sees the fully read body and can
+// veto it, as with `write_atomic_checked`.
+pub fn stage<R, F>(path: &Path, body: &mut R, check: F) -> io::Result<(Staged, u64)>
+where
+    R: Read,
+    F: FnOnce(&mut R) -> io::Result<()>,
+{
+    let parent = path
+        .parent()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No parent directory"))?;
+    let (temp_path, mut temp) = create_temp_file(parent)?;
+    let staged = Staged {
+        temp_path,
+        path: path.to_path_buf(),
+    };
+    let written = io::copy(body, &mut temp)?;
+    check(body)?;
+    temp.sync_all()?;
+    Ok((staged, written))
+}
+
+// Stores `body` as `dir/<first two hex digits>/<sha-256 hex>`, so identical
+// content is only ever stored once. `body` must hash with sha-256; `check`
+// sees every digest it computed and can veto the blob. Returns the hex digest
+// and whether the blob is new.
+pub fn store_blob<R, F>(
+    dir: &Path,
+    body: &mut HashingReader<R>,
+    check: F,
+) -> io::Result<(String, bool)>
+where
+    R: Read,
+    F: FnOnce(&[(DigestAlgorithm, Vec<u8>)]) -> io::Result<()>,
+{
+    std::fs::create_dir_all(dir)?;
+    let (temp_path, mut temp) = create_temp_file(dir)?;
+
+    let result = (|| {
+        io::copy(body, &mut temp)?;
+        let digests = body.digests();
+        check(&digests)?;
+        let hex = digests
+            .iter()
+            .find(|(algorithm, _)| *algorithm == DigestAlgorithm::Sha256)
+            .map(|(_, digest)| hex::encode(digest))
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "Body isn't hashed with sha-256")
+            })?;
+
+        let shard = dir.join(&hex[..2]);
+        std::fs::create_dir_all(&shard)?;
+        let path = shard.join(&hex);
+        if path.is_file() {
+            return Ok((hex, false));
+        }
+        temp.sync_all()?;
+        // Losing a race with an upload of the same content is fine, the blob
+        // that won is identical
+        let created = match std::fs::hard_link(&temp_path, &path) {
+            Ok(()) => true,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => false,
+            Err(e) => return Err(e),
+        };
+        sync_dir(&shard)?;
+        Ok((hex, created))
+    })();
+
+    let _ = std::fs::remove_file(&temp_path);
+    result
+}
+
+// Moves `from` to `to`. Whatever is at `to` is only set aside until `from`
+// is in place, then removed, so a failure leaves both as they were.
+pub fn replace_path(from: &Path, to: &Path) -> io::Result<()> {
+    let parent = to
+        .parent()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No parent directory"))?;
+    let backup = match std::fs::symlink_metadata(to) {
+        Ok(_) => {
+            let backup = temp_path(parent);
+            std::fs::rename(to, &backup)?;
+            Some(backup)
+        }
+        Err(_) => None,
+    };
+    if let Err(e) = std::fs::rename(from, to) {
+        if let Some(backup) = &backup {
+            let _ = std::fs::rename(backup, to);
+        }
+        return Err(e);
+    }
+    if let Some(backup) = backup {
+        let _ = remove_path(&backup);
+    }
+    sync_dir(parent)
+}
+
+// Removes a file, or a directory and everything in it
+pub fn remove_path(path: &Path) -> io::Result<()> {
+    if std::fs::symlink_metadata(path)?.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    }
+}
+
+// An unused name in `dir` for staging a file or directory
+pub fn temp_path(dir: &Path) -> PathBuf {
+    loop {
+        let path = dir.join(temp_name());
+        if std::fs::symlink_metadata(&path).is_err() {
+            return path;
+        }
+    }
+}
+
+fn temp_name() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    format!(
+        ".upload-{}-{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, AtomicOrdering::Relaxed)
+    )
+}
+
+fn create_temp_file(dir: &Path) -> io::Result<(PathBuf, File)> {
+    loop {
+        let temp_path = dir.join(temp_name());
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)
+        {
+            Ok(file) => return Ok((temp_path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Persists a rename or link by syncing the containing directory. Not every
+// platform lets a directory be opened for that, so failures are ignored.
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    if let Ok(dir) = File::open(dir) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn temp_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("files-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn entries(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn create_new_refuses_an_existing_file() {
+        let dir = temp_dir("create-new");
+        let path = dir.join("a.txt");
+
+        write_atomic(&path, &mut Cursor::new("first"), WriteMode::CreateNew).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "first");
+
+        let (staged, written) = stage(&path, &mut Cursor::new("second"), |_| Ok(())).unwrap();
+        assert_eq!(written, 6);
+        let e = staged.commit(WriteMode::CreateNew).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "first");
+        assert_eq!(entries(&dir), vec!["a.txt"]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn replace_swaps_the_content() {
+        let dir = temp_dir("replace");
+        let path = dir.join("a.txt");
+        std::fs::write(&path, "old").unwrap();
+
+        let (staged, _) = stage(&path, &mut Cursor::new("new"), |_| Ok(())).unwrap();
+        assert_eq!(staged.path(), path);
+        // Nothing is visible until the commit
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "old");
+        staged.commit(WriteMode::Replace).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(entries(&dir), vec!["a.txt"]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn dropping_or_vetoing_discards_the_body() {
+        let dir = temp_dir("discard");
+        let path = dir.join("a.txt");
+
+        let (staged, _) = stage(&path, &mut Cursor::new("body"), |_| Ok(())).unwrap();
+        assert_eq!(entries(&dir).len(), 1);
+        drop(staged);
+        assert!(entries(&dir).is_empty());
+
+        let veto = |_: &mut Cursor<&str>| Err(io::Error::new(io::ErrorKind::InvalidData, "no"));
+        let e = write_atomic_checked(&path, &mut Cursor::new("body"), WriteMode::Replace, veto)
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(entries(&dir).is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn stores_identical_blobs_once() {
+        let dir = temp_dir("blobs");
+        let store = |body: &str| {
+            let mut body =
+                HashingReader::new(Cursor::new(body.to_string()), &[DigestAlgorithm::Sha256]);
+            store_blob(&dir, &mut body, |_| Ok(())).unwrap()
+        };
+        let (hex, created) = store("blob");
+        assert!(created);
+        assert_eq!(store("blob"), (hex.clone(), false));
+        let path = dir.join(&hex[..2]).join(&hex);
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "blob");
+        assert_eq!(entries(&dir), vec![hex[..2].to_string()]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn replace_path_moves_directories() {
+        let dir = temp_dir("replace-path");
+        std::fs::create_dir_all(dir.join("from")).unwrap();
+        std::fs::write(dir.join("from/new.txt"), "new").unwrap();
+        std::fs::create_dir_all(dir.join("to")).unwrap();
+        std::fs::write(dir.join("to/old.txt"), "old").unwrap();
+
+        replace_path(&dir.join("from"), &dir.join("to")).unwrap();
+        assert_eq!(entries(&dir), vec!["to"]);
+        assert_eq!(entries(&dir.join("to")), vec!["new.txt"]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
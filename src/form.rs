@@ -0,0 +1,81 @@
+use crate::url::parse_query;
+
+// The fields of an application/x-www-form-urlencoded body or query string, in
+// order. A key may appear more than once.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FormData {
+    pairs: Vec<(String, String)>,
+}
+
+impl FormData {
+    pub fn parse(input: &str) -> FormData {
+        FormData {
+            pairs: parse_query(input),
+        }
+    }
+
+    // The first value of `key`
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.pairs
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        self.pairs
+            .iter()
+            .filter(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.pairs.iter().any(|(k, _)| k == key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn looks_up_fields() {
+        let form = FormData::parse("a=1&b=x+y&a=%262&flag");
+        assert_eq!(form.get("a"), Some("1"));
+        assert_eq!(form.get_all("a"), vec!["1", "&2"]);
+        assert_eq!(form.get("b"), Some("x y"));
+        assert_eq!(form.get("flag"), Some(""));
+        assert_eq!(form.get("c"), None);
+        assert!(form.get_all("c").is_empty());
+        assert!(form.contains_key("flag"));
+        assert!(!form.contains_key("c"));
+        assert_eq!(form.len(), 4);
+        assert!(!form.is_empty());
+        assert_eq!(
+            form.iter().collect::<Vec<_>>(),
+            vec![("a", "1"), ("b", "x y"), ("a", "&2"), ("flag", "")]
+        );
+    }
+
+    #[test]
+    fn empty_forms() {
+        for input in ["", "&&", "=%zz"] {
+            let form = FormData::parse(input);
+            assert!(form.is_empty(), "{}", input);
+            assert_eq!(form.len(), 0);
+        }
+    }
+}
@@ -1,13 +1,24 @@
 use std::fmt;
 use std::io::Error;
 use std::io::ErrorKind;
-use std::io::{Read, Write};
+use std::path::Path;
 
+// Named as they are sent
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum RequestMethod {
     GET,
     POST,
     PUT,
+    DELETE,
+    HEAD,
+    PATCH,
+    OPTIONS,
+    // WebDAV (RFC 4918)
+    PROPFIND,
+    MKCOL,
+    COPY,
+    MOVE,
 }
 
 impl RequestMethod {
@@ -16,6 +27,14 @@ impl RequestMethod {
             "GET" => Ok(RequestMethod::GET),
             "POST" => Ok(RequestMethod::POST),
             "PUT" => Ok(RequestMethod::PUT),
+            "DELETE" => Ok(RequestMethod::DELETE),
+            "HEAD" => Ok(RequestMethod::HEAD),
+            "PATCH" => Ok(RequestMethod::PATCH),
+            "OPTIONS" => Ok(RequestMethod::OPTIONS),
+            "PROPFIND" => Ok(RequestMethod::PROPFIND),
+            "MKCOL" => Ok(RequestMethod::MKCOL),
+            "COPY" => Ok(RequestMethod::COPY),
+            "MOVE" => Ok(RequestMethod::MOVE),
             _ => Err(Error::new(
                 ErrorKind::InvalidData,
                 format!("Invalid method: {}", method),
@@ -30,42 +49,103 @@ impl fmt::Display for RequestMethod {
             RequestMethod::GET => write!(f, "GET"),
             RequestMethod::POST => write!(f, "POST"),
             RequestMethod::PUT => write!(f, "PUT"),
+            RequestMethod::DELETE => write!(f, "DELETE"),
+            RequestMethod::HEAD => write!(f, "HEAD"),
+            RequestMethod::PATCH => write!(f, "PATCH"),
+            RequestMethod::OPTIONS => write!(f, "OPTIONS"),
+            RequestMethod::PROPFIND => write!(f, "PROPFIND"),
+            RequestMethod::MKCOL => write!(f, "MKCOL"),
+            RequestMethod::COPY => write!(f, "COPY"),
+            RequestMethod::MOVE => write!(f, "MOVE"),
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum StatusCode {
     Ok = 200,
     NotFound = 404,
+    MethodNotAllowed = 405,
     Created = 201,
+    NoContent = 204,
     InternalServerError = 500,
+    BadGateway = 502,
+    PartialContent = 206,
+    MultiStatus = 207,
+    NotModified = 304,
     BadRequest = 400,
+    Unauthorized = 401,
+    Forbidden = 403,
+    Conflict = 409,
+    Gone = 410,
+    PreconditionFailed = 412,
+    PayloadTooLarge = 413,
+    UnsupportedMediaType = 415,
+    RangeNotSatisfiable = 416,
+    ExpectationFailed = 417,
 }
 
 impl StatusCode {
-    pub fn to_u16(&self) -> u16 {
+    pub fn to_u16(self) -> u16 {
         match self {
             StatusCode::Ok => 200,
             StatusCode::NotFound => 404,
+            StatusCode::MethodNotAllowed => 405,
             StatusCode::Created => 201,
+            StatusCode::NoContent => 204,
             StatusCode::InternalServerError => 500,
+            StatusCode::BadGateway => 502,
+            StatusCode::PartialContent => 206,
+            StatusCode::MultiStatus => 207,
+            StatusCode::NotModified => 304,
             StatusCode::BadRequest => 400,
+            StatusCode::Unauthorized => 401,
+            StatusCode::Forbidden => 403,
+            StatusCode::Conflict => 409,
+            StatusCode::Gone => 410,
+            StatusCode::PreconditionFailed => 412,
+            StatusCode::PayloadTooLarge => 413,
+            StatusCode::UnsupportedMediaType => 415,
+            StatusCode::RangeNotSatisfiable => 416,
+            StatusCode::ExpectationFailed => 417,
         }
     }
 
-    pub fn to_reason_phrase(&self) -> &'static str {
+    pub fn to_reason_phrase(self) -> &'static str {
         match self {
             StatusCode::Ok => "OK",
             StatusCode::NotFound => "Not Found",
+            StatusCode::MethodNotAllowed => "Method Not Allowed",
             StatusCode::Created => "Created",
+            StatusCode::NoContent => "No Content",
             StatusCode::InternalServerError => "Internal Server Error",
+            StatusCode::BadGateway => "Bad Gateway",
+            StatusCode::PartialContent => "Partial Content",
+            StatusCode::MultiStatus => "Multi-Status",
+            StatusCode::NotModified => "Not Modified",
             StatusCode::BadRequest => "Bad Request",
+            StatusCode::Unauthorized => "Unauthorized",
+            StatusCode::Forbidden => "Forbidden",
+            StatusCode::Conflict => "Conflict",
+            StatusCode::Gone => "Gone",
+            StatusCode::PreconditionFailed => "Precondition Failed",
+            StatusCode::PayloadTooLarge => "Payload Too Large",
+            StatusCode::UnsupportedMediaType => "Unsupported Media Type",
+            StatusCode::RangeNotSatisfiable => "Range Not Satisfiable",
+            StatusCode::ExpectationFailed => "Expectation Failed",
         }
     }
 
+    // Display delegates here
+    #[allow(clippy::inherent_to_string_shadow_display, clippy::wrong_self_convention)]
     pub fn to_string(&self) -> String {
         format!("{} {}", self.to_u16(), self.to_reason_phrase())
     }
+
+    // Responses with these statuses never carry content
+    pub fn allows_body(self) -> bool {
+        !matches!(self, StatusCode::NoContent | StatusCode::NotModified)
+    }
 }
 
 impl fmt::Display for StatusCode {
@@ -97,6 +177,40 @@ pub enum Header {
     UserAgent,
     Host,
     Accept,
+    Vary,
+    Range,
+    IfRange,
+    AcceptRanges,
+    ContentRange,
+    ETag,
+    LastModified,
+    IfMatch,
+    IfNoneMatch,
+    IfModifiedSince,
+    IfUnmodifiedSince,
+    TransferEncoding,
+    Connection,
+    Location,
+    CacheControl,
+    TusResumable,
+    TusVersion,
+    TusExtension,
+    TusMaxSize,
+    UploadLength,
+    UploadOffset,
+    UploadMetadata,
+    UploadExpires,
+    Allow,
+    Dav,
+    Depth,
+    Destination,
+    Overwrite,
+    ContentDisposition,
+    Expect,
+    ContentDigest,
+    ReprDigest,
+    WantContentDigest,
+    WantReprDigest,
     Custom(String),
 }
 
@@ -110,10 +224,46 @@ impl Header {
             "user-agent" => Header::UserAgent,
             "host" => Header::Host,
             "accept" => Header::Accept,
+            "vary" => Header::Vary,
+            "range" => Header::Range,
+            "if-range" => Header::IfRange,
+            "accept-ranges" => Header::AcceptRanges,
+            "content-range" => Header::ContentRange,
+            "etag" => Header::ETag,
+            "last-modified" => Header::LastModified,
+            "if-match" => Header::IfMatch,
+            "if-none-match" => Header::IfNoneMatch,
+            "if-modified-since" => Header::IfModifiedSince,
+            "if-unmodified-since" => Header::IfUnmodifiedSince,
+            "transfer-encoding" => Header::TransferEncoding,
+            "connection" => Header::Connection,
+            "location" => Header::Location,
+            "cache-control" => Header::CacheControl,
+            "tus-resumable" => Header::TusResumable,
+            "tus-version" => Header::TusVersion,
+            "tus-extension" => Header::TusExtension,
+            "tus-max-size" => Header::TusMaxSize,
+            "upload-length" => Header::UploadLength,
+            "upload-offset" => Header::UploadOffset,
+            "upload-metadata" => Header::UploadMetadata,
+            "upload-expires" => Header::UploadExpires,
+            "allow" => Header::Allow,
+            "dav" => Header::Dav,
+            "depth" => Header::Depth,
+            "destination" => Header::Destination,
+            "overwrite" => Header::Overwrite,
+            "content-disposition" => Header::ContentDisposition,
+            "expect" => Header::Expect,
+            "content-digest" => Header::ContentDigest,
+            "repr-digest" => Header::ReprDigest,
+            "want-content-digest" => Header::WantContentDigest,
+            "want-repr-digest" => Header::WantReprDigest,
             _ => Header::Custom(header.to_string()),
         }
     }
 
+    // Display delegates here
+    #[allow(clippy::inherent_to_string_shadow_display)]
     pub fn to_string(&self) -> String {
         match self {
             Header::ContentLength => "Content-Length".to_string(),
@@ -123,6 +273,40 @@ impl Header {
             Header::UserAgent => "User-Agent".to_string(),
             Header::Host => "Host".to_string(),
             Header::Accept => "Accept".to_string(),
+            Header::Vary => "Vary".to_string(),
+            Header::Range => "Range".to_string(),
+            Header::IfRange => "If-Range".to_string(),
+            Header::AcceptRanges => "Accept-Ranges".to_string(),
+            Header::ContentRange => "Content-Range".to_string(),
+            Header::ETag => "ETag".to_string(),
+            Header::LastModified => "Last-Modified".to_string(),
+            Header::IfMatch => "If-Match".to_string(),
+            Header::IfNoneMatch => "If-None-Match".to_string(),
+            Header::IfModifiedSince => "If-Modified-Since".to_string(),
+            Header::IfUnmodifiedSince => "If-Unmodified-Since".to_string(),
+            Header::TransferEncoding => "Transfer-Encoding".to_string(),
+            Header::Connection => "Connection".to_string(),
+            Header::Location => "Location".to_string(),
+            Header::CacheControl => "Cache-Control".to_string(),
+            Header::TusResumable => "Tus-Resumable".to_string(),
+            Header::TusVersion => "Tus-Version".to_string(),
+            Header::TusExtension => "Tus-Extension".to_string(),
+            Header::TusMaxSize => "Tus-Max-Size".to_string(),
+            Header::UploadLength => "Upload-Length".to_string(),
+            Header::UploadOffset => "Upload-Offset".to_string(),
+            Header::UploadMetadata => "Upload-Metadata".to_string(),
+            Header::UploadExpires => "Upload-Expires".to_string(),
+            Header::Allow => "Allow".to_string(),
+            Header::Dav => "DAV".to_string(),
+            Header::Depth => "Depth".to_string(),
+            Header::Destination => "Destination".to_string(),
+            Header::Overwrite => "Overwrite".to_string(),
+            Header::ContentDisposition => "Content-Disposition".to_string(),
+            Header::Expect => "Expect".to_string(),
+            Header::ContentDigest => "Content-Digest".to_string(),
+            Header::ReprDigest => "Repr-Digest".to_string(),
+            Header::WantContentDigest => "Want-Content-Digest".to_string(),
+            Header::WantReprDigest => "Want-Repr-Digest".to_string(),
             Header::Custom(value) => value.clone(),
         }
     }
@@ -137,6 +321,8 @@ impl fmt::Display for Header {
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum ContentType {
     TextPlain,
+    TextHtml,
+    ApplicationJson,
     ApplicationOctetStream,
     Custom(String),
 }
@@ -145,18 +331,48 @@ impl ContentType {
     pub fn from_string(content_type: &str) -> Self {
         match content_type.to_lowercase().as_str() {
             "text/plain" => ContentType::TextPlain,
+            "text/html" => ContentType::TextHtml,
+            "application/json" => ContentType::ApplicationJson,
             "application/octet-stream" => ContentType::ApplicationOctetStream,
             _ => ContentType::Custom(content_type.to_string()),
         }
     }
 
+    // Display delegates here
+    #[allow(clippy::inherent_to_string_shadow_display)]
     pub fn to_string(&self) -> String {
         match self {
             ContentType::TextPlain => "text/plain".to_string(),
+            ContentType::TextHtml => "text/html".to_string(),
+            ContentType::ApplicationJson => "application/json".to_string(),
             ContentType::ApplicationOctetStream => "application/octet-stream".to_string(),
             ContentType::Custom(value) => value.clone(),
         }
     }
+
+    pub fn from_extension(extension: &str) -> Self {
+        match extension.to_lowercase().as_str() {
+            "txt" => ContentType::TextPlain,
+            "html" | "htm" => ContentType::TextHtml,
+            "json" => ContentType::ApplicationJson,
+            "css" => ContentType::Custom("text/css".to_string()),
+            "js" | "mjs" => ContentType::Custom("text/javascript".to_string()),
+            "svg" => ContentType::Custom("image/svg+xml".to_string()),
+            "png" => ContentType::Custom("image/png".to_string()),
+            "jpg" | "jpeg" => ContentType::Custom("image/jpeg".to_string()),
+            "gif" => ContentType::Custom("image/gif".to_string()),
+            "wasm" => ContentType::Custom("application/wasm".to_string()),
+            "pdf" => ContentType::Custom("application/pdf".to_string()),
+            _ => ContentType::ApplicationOctetStream,
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Self {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .map(ContentType::from_extension)
+            .unwrap_or(ContentType::ApplicationOctetStream)
+    }
 }
 
 impl fmt::Display for ContentType {
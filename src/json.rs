@@ -0,0 +1,547 @@
+use std::collections::HashMap;
+use std::fmt;
+use thiserror::Error;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+    pub fn object<K: Into<String>>(fields: Vec<(K, JsonValue)>) -> Self {
+        JsonValue::Object(fields.into_iter().map(|(k, v)| (k.into(), v)).collect())
+    }
+}
+
+impl From<&str> for JsonValue {
+    fn from(value: &str) -> Self {
+        JsonValue::String(value.to_string())
+    }
+}
+
+impl From<String> for JsonValue {
+    fn from(value: String) -> Self {
+        JsonValue::String(value)
+    }
+}
+
+impl From<bool> for JsonValue {
+    fn from(value: bool) -> Self {
+        JsonValue::Bool(value)
+    }
+}
+
+impl From<u64> for JsonValue {
+    fn from(value: u64) -> Self {
+        JsonValue::Number(value as f64)
+    }
+}
+
+impl fmt::Display for JsonValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonValue::Null => write!(f, "null"),
+            JsonValue::Bool(value) => write!(f, "{}", value),
+            JsonValue::Number(value) if value.is_finite() => write!(f, "{}", value),
+            JsonValue::Number(_) => write!(f, "null"),
+            JsonValue::String(value) => write_string(f, value),
+            JsonValue::Array(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            JsonValue::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, value: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in value.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+// Nesting deeper than this is rejected rather than risking the stack
+const MAX_DEPTH: usize = 128;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum JsonError {
+    #[error("invalid JSON at byte {position}: {message}")]
+    Syntax {
+        position: usize,
+        message: &'static str,
+    },
+    #[error("expected {expected}")]
+    Type { expected: &'static str },
+}
+
+impl JsonValue {
+    pub fn parse(input: &str) -> Result<JsonValue, JsonError> {
+        let mut parser = Parser {
+            input: input.as_bytes(),
+            position: 0,
+        };
+        parser.skip_whitespace();
+        let value = parser.parse_value(0)?;
+        parser.skip_whitespace();
+        if parser.position != parser.input.len() {
+            return Err(parser.error("trailing characters"));
+        }
+        Ok(value)
+    }
+}
+
+// Conversion from a parsed JSON document, implemented by types a handler wants
+// to receive from a JSON body.
+pub trait FromJson: Sized {
+    fn from_json(value: &JsonValue) -> Result<Self, JsonError>;
+}
+
+impl FromJson for JsonValue {
+    fn from_json(value: &JsonValue) -> Result<Self, JsonError> {
+        Ok(value.clone())
+    }
+}
+
+impl FromJson for String {
+    fn from_json(value: &JsonValue) -> Result<Self, JsonError> {
+        match value {
+            JsonValue::String(value) => Ok(value.clone()),
+            _ => Err(JsonError::Type {
+                expected: "a string",
+            }),
+        }
+    }
+}
+
+impl FromJson for bool {
+    fn from_json(value: &JsonValue) -> Result<Self, JsonError> {
+        match value {
+            JsonValue::Bool(value) => Ok(*value),
+            _ => Err(JsonError::Type {
+                expected: "a boolean",
+            }),
+        }
+    }
+}
+
+impl FromJson for f64 {
+    fn from_json(value: &JsonValue) -> Result<Self, JsonError> {
+        match value {
+            JsonValue::Number(value) => Ok(*value),
+            _ => Err(JsonError::Type {
+                expected: "a number",
+            }),
+        }
+    }
+}
+
+macro_rules! impl_from_json_integer {
+    ($($t:ty),*) => {
+        $(
+            impl FromJson for $t {
+                fn from_json(value: &JsonValue) -> Result<Self, JsonError> {
+                    match value {
+                        // MAX may round up to the next power of two as a
+                        // float, which is already out of range
+                        JsonValue::Number(n)
+                            if n.fract() == 0.0
+                                && *n >= <$t>::MIN as f64
+                                && *n < <$t>::MAX as f64 + 1.0 =>
+                        {
+                            Ok(*n as $t)
+                        }
+                        _ => Err(JsonError::Type { expected: concat!("an integer fitting ", stringify!($t)) }),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_from_json_integer!(i32, i64, u8, u16, u32, u64, usize);
+
+impl<T: FromJson> FromJson for Option<T> {
+    fn from_json(value: &JsonValue) -> Result<Self, JsonError> {
+        match value {
+            JsonValue::Null => Ok(None),
+            value => T::from_json(value).map(Some),
+        }
+    }
+}
+
+impl<T: FromJson> FromJson for Vec<T> {
+    fn from_json(value: &JsonValue) -> Result<Self, JsonError> {
+        match value {
+            JsonValue::Array(values) => values.iter().map(T::from_json).collect(),
+            _ => Err(JsonError::Type {
+                expected: "an array",
+            }),
+        }
+    }
+}
+
+impl<T: FromJson> FromJson for HashMap<String, T> {
+    fn from_json(value: &JsonValue) -> Result<Self, JsonError> {
+        match value {
+            JsonValue::Object(fields) => fields
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), T::from_json(value)?)))
+                .collect(),
+            _ => Err(JsonError::Type {
+                expected: "an object",
+            }),
+        }
+    }
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &'static str) -> JsonError {
+        JsonError::Syntax {
+            position: self.position,
+            message,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.position).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.position += 1;
+        }
+    }
+
+    fn expect_literal(
+        &mut self,
+        literal: &'static str,
+        value: JsonValue,
+    ) -> Result<JsonValue, JsonError> {
+        if self.input[self.position..].starts_with(literal.as_bytes()) {
+            self.position += literal.len();
+            Ok(value)
+        } else {
+            Err(self.error("unexpected character"))
+        }
+    }
+
+    fn parse_value(&mut self, depth: usize) -> Result<JsonValue, JsonError> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("nesting too deep"));
+        }
+        match self.peek() {
+            Some(b'n') => self.expect_literal("null", JsonValue::Null),
+            Some(b't') => self.expect_literal("true", JsonValue::Bool(true)),
+            Some(b'f') => self.expect_literal("false", JsonValue::Bool(false)),
+            Some(b'"') => self.parse_string().map(JsonValue::String),
+            Some(b'[') => self.parse_array(depth),
+            Some(b'{') => self.parse_object(depth),
+            Some(b'-' | b'0'..=b'9') => self.parse_number(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn parse_array(&mut self, depth: usize) -> Result<JsonValue, JsonError> {
+        self.position += 1;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.position += 1;
+            return Ok(JsonValue::Array(values));
+        }
+        loop {
+            self.skip_whitespace();
+            values.push(self.parse_value(depth + 1)?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b']') => {
+                    self.position += 1;
+                    return Ok(JsonValue::Array(values));
+                }
+                _ => return Err(self.error("expected `,` or `]`")),
+            }
+        }
+    }
+
+    fn parse_object(&mut self, depth: usize) -> Result<JsonValue, JsonError> {
+        self.position += 1;
+        let mut fields = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.position += 1;
+            return Ok(JsonValue::Object(fields));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected a string key"));
+            }
+            let key = self.parse_string()?;
+            self.skip_whitespace();
+            if self.peek() != Some(b':') {
+                return Err(self.error("expected `:`"));
+            }
+            self.position += 1;
+            self.skip_whitespace();
+            let value = self.parse_value(depth + 1)?;
+            fields.push((key, value));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b'}') => {
+                    self.position += 1;
+                    return Ok(JsonValue::Object(fields));
+                }
+                _ => return Err(self.error("expected `,` or `}`")),
+            }
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<JsonValue, JsonError> {
+        let start = self.position;
+        if self.peek() == Some(b'-') {
+            self.position += 1;
+        }
+        let digits = |parser: &mut Self| {
+            let from = parser.position;
+            while let Some(b'0'..=b'9') = parser.peek() {
+                parser.position += 1;
+            }
+            parser.position > from
+        };
+        if self.peek() == Some(b'0') {
+            self.position += 1;
+        } else if !digits(self) {
+            return Err(self.error("invalid number"));
+        }
+        if self.peek() == Some(b'.') {
+            self.position += 1;
+            if !digits(self) {
+                return Err(self.error("invalid number"));
+            }
+        }
+        if let Some(b'e' | b'E') = self.peek() {
+            self.position += 1;
+            if let Some(b'+' | b'-') = self.peek() {
+                self.position += 1;
+            }
+            if !digits(self) {
+                return Err(self.error("invalid number"));
+            }
+        }
+        std::str::from_utf8(&self.input[start..self.position])
+            .ok()
+            .and_then(|number| number.parse::<f64>().ok())
+            .map(JsonValue::Number)
+            .ok_or_else(|| self.error("invalid number"))
+    }
+
+    fn parse_string(&mut self) -> Result<String, JsonError> {
+        self.position += 1;
+        let mut value = Vec::new();
+        loop {
+            match self.peek() {
+                None => return Err(self.error("unterminated string")),
+                Some(b'"') => {
+                    self.position += 1;
+                    return String::from_utf8(value).map_err(|_| self.error("invalid UTF-8"));
+                }
+                Some(b'\\') => {
+                    self.position += 1;
+                    let escaped = match self.peek() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            self.position += 1;
+                            let c = self.parse_unicode_escape()?;
+                            let mut utf8 = [0u8; 4];
+                            value.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+                            continue;
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    self.position += 1;
+                    let mut utf8 = [0u8; 4];
+                    value.extend_from_slice(escaped.encode_utf8(&mut utf8).as_bytes());
+                }
+                Some(c) if c < 0x20 => return Err(self.error("control character in string")),
+                Some(c) => {
+                    value.push(c);
+                    self.position += 1;
+                }
+            }
+        }
+    }
+
+    // Parses the XXXX of \uXXXX, combining surrogate pairs
+    fn parse_unicode_escape(&mut self) -> Result<char, JsonError> {
+        let high = self.parse_hex4()?;
+        if (0xD800..0xDC00).contains(&high) {
+            if !self.input[self.position..].starts_with(b"\\u") {
+                return Err(self.error("unpaired surrogate"));
+            }
+            self.position += 2;
+            let low = self.parse_hex4()?;
+            if !(0xDC00..0xE000).contains(&low) {
+                return Err(self.error("unpaired surrogate"));
+            }
+            let code = 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00);
+            return char::from_u32(code).ok_or_else(|| self.error("invalid escape"));
+        }
+        char::from_u32(high).ok_or_else(|| self.error("unpaired surrogate"))
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, JsonError> {
+        let hex = self
+            .input
+            .get(self.position..self.position + 4)
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+            .ok_or_else(|| self.error("invalid escape"))?;
+        self.position += 4;
+        Ok(hex)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> JsonValue {
+        JsonValue::parse(input).unwrap()
+    }
+
+    fn syntax_error(input: &str) -> (usize, &'static str) {
+        match JsonValue::parse(input) {
+            Err(JsonError::Syntax { position, message }) => (position, message),
+            other => panic!("{:?} parsed as {:?}", input, other),
+        }
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!(parse("null"), JsonValue::Null);
+        assert_eq!(parse(" true "), JsonValue::Bool(true));
+        assert_eq!(parse("-0.5e2"), JsonValue::Number(-50.0));
+        assert_eq!(parse("[]"), JsonValue::Array(Vec::new()));
+        assert_eq!(
+            parse(r#"{"a": {"b": [1, "x"]}, "a": false}"#),
+            JsonValue::object(vec![
+                (
+                    "a",
+                    JsonValue::object(vec![(
+                        "b",
+                        JsonValue::Array(vec![JsonValue::Number(1.0), "x".into()])
+                    )])
+                ),
+                ("a", false.into()),
+            ])
+        );
+    }
+
+    #[test]
+    fn strings_and_escapes() {
+        assert_eq!(parse(r#""a\"\\\/\b\f\n\r\t""#), "a\"\\/\u{8}\u{c}\n\r\t".into());
+        assert_eq!(parse(r#""\u00e9\u20AC""#), "é€".into());
+        assert_eq!(parse(r#""\ud83d\ude00""#), "😀".into());
+        assert_eq!(parse("\"ü\""), "ü".into());
+
+        let value = JsonValue::from("quote \" tab \t bell \u{7}");
+        assert_eq!(value.to_string(), r#""quote \" tab \t bell \u0007""#);
+        assert_eq!(parse(&value.to_string()), value);
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert_eq!(syntax_error(""), (0, "unexpected end of input"));
+        assert_eq!(syntax_error("nul"), (0, "unexpected character"));
+        assert_eq!(syntax_error("[1,]"), (3, "unexpected character"));
+        assert_eq!(syntax_error("[1 2]"), (3, "expected `,` or `]`"));
+        assert_eq!(syntax_error("{1: 2}"), (1, "expected a string key"));
+        assert_eq!(syntax_error(r#"{"a" 2}"#), (5, "expected `:`"));
+        assert_eq!(syntax_error("01"), (1, "trailing characters"));
+        assert_eq!(syntax_error("1."), (2, "invalid number"));
+        assert_eq!(syntax_error("-"), (1, "invalid number"));
+        assert_eq!(syntax_error("\"abc"), (4, "unterminated string"));
+        assert_eq!(syntax_error("\"a\nb\""), (2, "control character in string"));
+        assert_eq!(syntax_error(r#""\x""#), (2, "invalid escape"));
+        assert_eq!(syntax_error(r#""\u+0af""#), (3, "invalid escape"));
+        assert_eq!(syntax_error(r#""\ud83d""#), (7, "unpaired surrogate"));
+        assert_eq!(syntax_error(r#""\ude00""#), (7, "unpaired surrogate"));
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(JsonValue::parse(&nested(MAX_DEPTH + 1)).is_ok());
+        assert_eq!(syntax_error(&nested(MAX_DEPTH + 2)).1, "nesting too deep");
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(String::from_json(&parse(r#""x""#)), Ok("x".to_string()));
+        assert_eq!(u8::from_json(&parse("255")), Ok(255));
+        assert_eq!(i32::from_json(&parse("-255")), Ok(-255));
+        assert_eq!(
+            Vec::<String>::from_json(&parse(r#"["a"]"#)),
+            Ok(vec!["a".to_string()])
+        );
+        assert_eq!(Option::<String>::from_json(&parse("null")), Ok(None));
+        assert_eq!(Option::<u32>::from_json(&parse("7")), Ok(Some(7)));
+        assert_eq!(
+            Vec::<u8>::from_json(&parse(r#""x""#)),
+            Err(JsonError::Type {
+                expected: "an array"
+            })
+        );
+        assert!(u8::from_json(&parse("256")).is_err());
+        assert!(u64::from_json(&JsonValue::Number(18446744073709551616.0)).is_err());
+        assert!(u32::from_json(&JsonValue::Number(1.5)).is_err());
+        assert!(String::from_json(&JsonValue::Null).is_err());
+    }
+}
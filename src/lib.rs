@@ -0,0 +1,30 @@
+pub mod archive;
+pub mod body;
+pub mod conditional;
+pub mod config;
+pub mod date;
+pub mod digest;
+pub mod encoding;
+pub mod error_pages;
+pub mod extract;
+pub mod files;
+pub mod form;
+pub mod request;
+pub mod response;
+pub mod routes;
+pub mod server;
+pub mod http;
+pub mod json;
+pub mod listing;
+pub mod middleware;
+pub mod multipart;
+pub mod pattern;
+pub mod range;
+pub mod regex;
+pub mod router;
+pub mod sandbox;
+pub mod state;
+pub mod tus;
+pub mod url;
+pub mod vhost;
+pub mod webdav;
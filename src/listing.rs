@@ -0,0 +1,183 @@
+use crate::json::JsonValue;
+use crate::url::percent_encode;
+use std::cmp::Ordering;
+use std::fmt::Write;
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    Other,
+}
+
+impl EntryKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EntryKind::File => "file",
+            EntryKind::Directory => "directory",
+            EntryKind::Other => "other",
+        }
+    }
+}
+
+pub struct Entry {
+    pub name: String,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    pub kind: EntryKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortKey {
+    Name,
+    Size,
+    Modified,
+}
+
+impl SortKey {
+    pub fn from_string(key: &str) -> Option<Self> {
+        match key {
+            "name" => Some(SortKey::Name),
+            "size" => Some(SortKey::Size),
+            "mtime" | "modified" => Some(SortKey::Modified),
+            _ => None,
+        }
+    }
+}
+
+// Lists `dir`, skipping dotfiles. Entries whose metadata can't be read (e.g.
+// dangling symlinks) are listed as `Other` with size 0.
+pub fn read_dir(dir: &Path) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = match entry.file_name().into_string() {
+            Ok(name) if !name.starts_with('.') => name,
+            _ => continue,
+        };
+        let (size, modified, kind) = match std::fs::metadata(entry.path()) {
+            Ok(metadata) if metadata.is_dir() => {
+                (0, metadata.modified().ok(), EntryKind::Directory)
+            }
+            Ok(metadata) if metadata.is_file() => {
+                (metadata.len(), metadata.modified().ok(), EntryKind::File)
+            }
+            Ok(metadata) => (0, metadata.modified().ok(), EntryKind::Other),
+            Err(_) => (0, None, EntryKind::Other),
+        };
+        entries.push(Entry {
+            name,
+            size,
+            modified,
+            kind,
+        });
+    }
+    Ok(entries)
+}
+
+// Sorts directories before everything else, then by `key`
+pub fn sort(entries: &mut [Entry], key: SortKey, descending: bool) {
+    entries.sort_by(|a, b| {
+        let directories_first =
+            (b.kind == EntryKind::Directory).cmp(&(a.kind == EntryKind::Directory));
+        let ordering = match key {
+            SortKey::Name => a.name.cmp(&b.name),
+            SortKey::Size => a.size.cmp(&b.size).then_with(|| a.name.cmp(&b.name)),
+            SortKey::Modified => a
+                .modified
+                .cmp(&b.modified)
+                .then_with(|| a.name.cmp(&b.name)),
+        };
+        let ordering = if descending {
+            ordering.reverse()
+        } else {
+            ordering
+        };
+        match directories_first {
+            Ordering::Equal => ordering,
+            other => other,
+        }
+    });
+}
+
+pub fn to_json(entries: &[Entry]) -> String {
+    let entries = entries
+        .iter()
+        .map(|entry| {
+            let modified = entry
+                .modified
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|modified| JsonValue::from(modified.as_secs()))
+                .unwrap_or(JsonValue::Null);
+            JsonValue::object(vec![
+                ("name", JsonValue::from(entry.name.as_str())),
+                ("size", JsonValue::from(entry.size)),
+                ("mtime", modified),
+                ("type", JsonValue::from(entry.kind.as_str())),
+            ])
+        })
+        .collect();
+    JsonValue::Array(entries).to_string()
+}
+
+// Renders an HTML index. `base` is the URL path of the directory, ending in `/`.
+pub fn to_html(entries: &[Entry], base: &str, has_parent: bool) -> String {
+    let title = escape_html(base);
+    let mut html = String::new();
+    write!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {title}</title></head>\n<body>\n<h1>Index of {title}</h1>\n<table>\n<tr><th><a href=\"?sort=name\">Name</a></th><th><a href=\"?sort=size\">Size</a></th><th><a href=\"?sort=mtime\">Modified</a></th></tr>\n"
+    )
+    .unwrap();
+    if has_parent {
+        html.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+    }
+    for entry in entries {
+        let suffix = if entry.kind == EntryKind::Directory {
+            "/"
+        } else {
+            ""
+        };
+        let modified = entry
+            .modified
+            .map(crate::date::format_http_date)
+            .unwrap_or_default();
+        let size = if entry.kind == EntryKind::File {
+            entry.size.to_string()
+        } else {
+            "-".to_string()
+        };
+        writeln!(
+            html,
+            "<tr><td><a href=\"{}{}{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>",
+            escape_html(base),
+            percent_encode(&entry.name),
+            suffix,
+            escape_html(&entry.name),
+            suffix,
+            size,
+            modified
+        )
+        .unwrap();
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+pub fn escape_html(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -1,74 +1,55 @@
-mod encoding;
-mod request;
-mod response;
-mod routes;
-mod server;
-mod http;
-mod router;
-
-use crate::routes::{root_handler, echo_handler, user_agent_handler, files_handler, files_handler_create};
-use crate::server::{HttpServer};
-use crate::http::{RequestMethod};
-use crate::router::Router;
-use std::sync::{Arc, RwLock};
-use std::net::TcpListener;
-
-fn main() {
-    let mut router = Router::new();
+use http_server_starter_rust::config::Config;
+use http_server_starter_rust::error_pages::ErrorPages;
+use http_server_starter_rust::routes;
+use http_server_starter_rust::server::HttpServer;
+use http_server_starter_rust::state::AppState;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::RwLock;
+
+#[tokio::main]
+async fn main() {
+    let config = Config::from_args(std::env::args());
+    let router = routes::router(&config);
+    let max_body_size = config.max_body_size;
+    let error_pages = config.error_pages.as_ref().and_then(|dir| match ErrorPages::load(dir) {
+        Ok(pages) => Some(pages),
+        Err(e) => {
+            eprintln!("Error: cannot load error pages from {}: {}", dir.display(), e);
+            None
+        }
+    });
+    let virtual_hosts = config
+        .virtual_hosts
+        .iter()
+        .map(|(pattern, directory)| {
+            let config = config.with_directory(directory.clone());
+            let router = routes::router(&config);
+            let state = AppState::new(config, router.urls());
+            (pattern.clone(), router, state)
+        })
+        .collect::<Vec<_>>();
+    let state = AppState::new(config, router.urls());
     let router_arc = Arc::new(RwLock::new(router));
 
-    {
-        let router_arc_clone = Arc::clone(&router_arc);
-        router_arc
-            .write()
-            .unwrap()
-            .add_route(RequestMethod::GET, "/", root_handler);
-    }
-
-    {
-        let router_arc_clone = Arc::clone(&router_arc);
-        router_arc
-            .write()
-            .unwrap()
-            .add_route(RequestMethod::GET, "/echo", echo_handler);
-    }
-
-    {
-        let router_arc_clone = Arc::clone(&router_arc);
-        router_arc
-            .write()
-            .unwrap()
-            .add_route(RequestMethod::GET, "/user-agent", user_agent_handler);
-    }
-
-    {
-        let router_arc_clone = Arc::clone(&router_arc);
-        router_arc
-            .write()
-            .unwrap()
-            .add_route(RequestMethod::GET, "/files", files_handler);
+    let mut server = HttpServer::new(router_arc.clone(), state)
+        .max_body_size(max_body_size)
+        .error_pages(error_pages);
+    for (pattern, router, state) in virtual_hosts {
+        server = server.virtual_host(&pattern, router, state);
     }
-
-    {
-        let router_arc_clone = Arc::clone(&router_arc);
-        router_arc
-            .write()
-            .unwrap()
-            .add_route(RequestMethod::POST, "/files", files_handler_create);
-    }
-
-    let server = Arc::new(HttpServer::new(router_arc.clone())); // Wrap HttpServer in Arc
+    let server = Arc::new(server); // Wrap HttpServer in Arc
 
     // Start the server
-    let listener = TcpListener::bind("127.0.0.1:4221").unwrap();
+    let listener = TcpListener::bind("127.0.0.1:4221").await.unwrap();
     println!("Listening on http://127.0.0.1:4221");
 
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
                 let server = Arc::clone(&server); // Clone the Arc reference
-                std::thread::spawn(move || {
-                    server.handle_client(stream); // Use the cloned server
+                tokio::spawn(async move {
+                    server.handle_client(stream).await; // Use the cloned server
                 });
             }
             Err(e) => {
@@ -0,0 +1,47 @@
+use crate::request::Request;
+use crate::response::Response;
+use crate::server::BoxFuture;
+use std::sync::Arc;
+
+// Wraps request handling: it can inspect or change the request, answer it
+// itself, or await `next.run(req)` and then inspect or change the response.
+pub type Middleware = Arc<
+    dyn for<'a> Fn(Request, Next<'a>) -> BoxFuture<'a, Result<Response, Response>> + Send + Sync,
+>;
+
+type Endpoint<'a> = dyn Fn(Request) -> BoxFuture<'a, Result<Response, Response>> + Send + Sync + 'a;
+
+// The rest of the chain after the middleware being run
+pub struct Next<'a> {
+    middleware: &'a [Middleware],
+    endpoint: &'a Endpoint<'a>,
+}
+
+impl<'a> Next<'a> {
+    pub fn new(middleware: &'a [Middleware], endpoint: &'a Endpoint<'a>) -> Next<'a> {
+        Next {
+            middleware,
+            endpoint,
+        }
+    }
+
+    pub fn run(self, req: Request) -> BoxFuture<'a, Result<Response, Response>> {
+        match self.middleware.split_first() {
+            Some((first, rest)) => first(req, Next::new(rest, self.endpoint)),
+            None => (self.endpoint)(req),
+        }
+    }
+}
+
+// Logs the method, target and status of every request to stderr
+pub fn log_requests(req: Request, next: Next<'_>) -> BoxFuture<'_, Result<Response, Response>> {
+    Box::pin(async move {
+        let line = format!("{} {}", req.method, req.target);
+        let result = next.run(req).await;
+        let status = match &result {
+            Ok(response) | Err(response) => response.status.code.to_u16(),
+        };
+        eprintln!("{} {}", line, status);
+        result
+    })
+}
@@ -0,0 +1,395 @@
+use crate::http::Header;
+use crate::url::percent_decode;
+use std::collections::HashMap;
+use std::io::{self, Error, ErrorKind, Read};
+
+// Limits on part headers, which are buffered unlike part bodies
+const MAX_HEADER_LINE: usize = 8 * 1024;
+const MAX_HEADERS: usize = 32;
+const READ_SIZE: usize = 16 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    // Before the first delimiter
+    Preamble,
+    // Just past a delimiter; part headers or the close delimiter follow
+    Delimiter,
+    // Reading the body of the current part
+    Body,
+    // Past the close delimiter
+    Done,
+}
+
+// Streaming parser for multipart bodies (RFC 7578). Parts are handed out one
+// at a time and read directly from the underlying reader; only a window the
+// size of the delimiter is kept back to detect part boundaries.
+pub struct Multipart<R: Read> {
+    reader: R,
+    // "\r\n--" followed by the boundary
+    delimiter: Vec<u8>,
+    buffer: Vec<u8>,
+    eof: bool,
+    state: State,
+}
+
+pub struct Part<'a, R: Read> {
+    multipart: &'a mut Multipart<R>,
+    pub headers: HashMap<Header, String>,
+    pub name: Option<String>,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+}
+
+impl<R: Read> Multipart<R> {
+    pub fn new(reader: R, boundary: &str) -> Multipart<R> {
+        let mut delimiter = b"\r\n--".to_vec();
+        delimiter.extend_from_slice(boundary.as_bytes());
+        Multipart {
+            reader,
+            delimiter,
+            // The first delimiter needn't be preceded by a line break, so
+            // pretend there is one
+            buffer: b"\r\n".to_vec(),
+            eof: false,
+            state: State::Preamble,
+        }
+    }
+
+    // Advances to the next part, skipping whatever is left of the current one
+    pub fn next_part(&mut self) -> io::Result<Option<Part<'_, R>>> {
+        loop {
+            match self.state {
+                State::Preamble | State::Body => {
+                    let mut sink = [0u8; READ_SIZE];
+                    while self.read_body(&mut sink)? > 0 {}
+                }
+                State::Delimiter => break,
+                State::Done => return Ok(None),
+            }
+        }
+
+        // "--" right after the delimiter closes the body
+        self.fill(2)?;
+        if self.buffer.starts_with(b"--") {
+            self.state = State::Done;
+            return Ok(None);
+        }
+        // Transport padding, then the line break ending the delimiter line
+        let line = self.read_line()?;
+        if !line.trim().is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Invalid multipart delimiter",
+            ));
+        }
+
+        let mut headers = HashMap::new();
+        loop {
+            let line = self.read_line()?;
+            if line.is_empty() {
+                break;
+            }
+            if headers.len() >= MAX_HEADERS {
+                return Err(Error::new(ErrorKind::InvalidData, "Too many part headers"));
+            }
+            if let Some((key, value)) = line.split_once(':') {
+                headers.insert(Header::from_string(key.trim()), value.trim().to_string());
+            }
+        }
+        self.state = State::Body;
+
+        let disposition = headers
+            .get(&Header::ContentDisposition)
+            .map(|value| parse_disposition(value))
+            .unwrap_or_default();
+        let content_type = headers.get(&Header::ContentType).cloned();
+
+        Ok(Some(Part {
+            multipart: self,
+            headers,
+            name: disposition.get("name").cloned(),
+            filename: disposition.get("filename").cloned(),
+            content_type,
+        }))
+    }
+
+    // Reads part data up to the next delimiter
+    fn read_body(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !matches!(self.state, State::Preamble | State::Body) || buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if let Some(position) = find(&self.buffer, &self.delimiter) {
+                if position == 0 {
+                    self.buffer.drain(..self.delimiter.len());
+                    self.state = State::Delimiter;
+                    return Ok(0);
+                }
+                return Ok(self.take(buf, position));
+            }
+            // Everything but a possible delimiter prefix at the end is data
+            let safe = self.buffer.len().saturating_sub(self.delimiter.len() - 1);
+            if safe > 0 {
+                return Ok(self.take(buf, safe));
+            }
+            if self.eof {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "Truncated multipart body",
+                ));
+            }
+            self.fill_more()?;
+        }
+    }
+
+    fn take(&mut self, buf: &mut [u8], available: usize) -> usize {
+        let n = available.min(buf.len());
+        buf[..n].copy_from_slice(&self.buffer[..n]);
+        self.buffer.drain(..n);
+        n
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        loop {
+            if let Some(position) = find(&self.buffer, b"\r\n") {
+                let line: Vec<u8> = self.buffer.drain(..position + 2).take(position).collect();
+                return String::from_utf8(line).map_err(|e| Error::new(ErrorKind::InvalidData, e));
+            }
+            if self.buffer.len() > MAX_HEADER_LINE {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "Part header line too long",
+                ));
+            }
+            if self.eof {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "Truncated multipart body",
+                ));
+            }
+            self.fill_more()?;
+        }
+    }
+
+    fn fill(&mut self, len: usize) -> io::Result<()> {
+        while self.buffer.len() < len && !self.eof {
+            self.fill_more()?;
+        }
+        Ok(())
+    }
+
+    fn fill_more(&mut self) -> io::Result<()> {
+        let mut chunk = [0u8; READ_SIZE];
+        let read = self.reader.read(&mut chunk)?;
+        if read == 0 {
+            self.eof = true;
+        }
+        self.buffer.extend_from_slice(&chunk[..read]);
+        Ok(())
+    }
+}
+
+impl<R: Read> Part<'_, R> {
+    pub fn is_file(&self) -> bool {
+        self.filename.is_some()
+    }
+}
+
+impl<R: Read> Read for Part<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.multipart.read_body(buf)
+    }
+}
+
+// Parses `form-data; name="a"; filename="b"` into its parameters, decoding
+// RFC 5987 `filename*=UTF-8''...` values in preference to plain ones.
+fn parse_disposition(value: &str) -> HashMap<String, String> {
+    let mut params = HashMap::new();
+    let mut rest = value.split_once(';').map(|(_, rest)| rest).unwrap_or("");
+    while !rest.is_empty() {
+        let (key, after_key) = match rest.split_once('=') {
+            Some((key, after_key)) => (key.trim().to_lowercase(), after_key.trim_start()),
+            None => break,
+        };
+        let (value, remaining) = if let Some(quoted) = after_key.strip_prefix('"') {
+            let mut value = String::new();
+            let mut chars = quoted.char_indices();
+            let mut end = quoted.len();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '\\' => {
+                        if let Some((_, escaped)) = chars.next() {
+                            value.push(escaped);
+                        }
+                    }
+                    '"' => {
+                        end = i + 1;
+                        break;
+                    }
+                    c => value.push(c),
+                }
+            }
+            let remaining = quoted[end..].split_once(';').map(|(_, r)| r).unwrap_or("");
+            (value, remaining)
+        } else {
+            let (value, remaining) = after_key.split_once(';').unwrap_or((after_key, ""));
+            (value.trim().to_string(), remaining)
+        };
+
+        match key.strip_suffix('*') {
+            Some(key) => {
+                let encoded = value.splitn(3, '\'').nth(2).unwrap_or_default();
+                if let Ok(decoded) = percent_decode(encoded) {
+                    params.insert(key.to_string(), decoded);
+                }
+            }
+            None => {
+                params.entry(key).or_insert(value);
+            }
+        }
+        rest = remaining;
+    }
+    params
+}
+
+// Extracts the boundary from a `multipart/form-data; boundary=...` value
+pub fn boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    let media_type = params.next()?.trim().to_lowercase();
+    if !media_type.starts_with("multipart/") {
+        return None;
+    }
+    params
+        .filter_map(|param| param.split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value.trim().trim_matches('"').to_string())
+        .filter(|boundary| !boundary.is_empty() && boundary.len() <= 70)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_parts(body: &[u8], boundary: &str) -> io::Result<Vec<(Option<String>, Vec<u8>)>> {
+        let mut multipart = Multipart::new(body, boundary);
+        let mut parts = Vec::new();
+        while let Some(mut part) = multipart.next_part()? {
+            let mut data = Vec::new();
+            part.read_to_end(&mut data)?;
+            parts.push((part.filename.clone(), data));
+        }
+        Ok(parts)
+    }
+
+    #[test]
+    fn boundaries() {
+        assert_eq!(
+            boundary("multipart/form-data; boundary=abc").as_deref(),
+            Some("abc")
+        );
+        assert_eq!(
+            boundary("Multipart/Form-Data; charset=utf-8; BOUNDARY=\"a b\"").as_deref(),
+            Some("a b")
+        );
+        assert_eq!(boundary("text/plain; boundary=abc"), None);
+        assert_eq!(boundary("multipart/form-data"), None);
+        assert_eq!(boundary("multipart/form-data; boundary="), None);
+        assert_eq!(boundary(&format!("multipart/mixed; boundary={}", "x".repeat(71))), None);
+    }
+
+    #[test]
+    fn parts_split_on_the_delimiter_only() {
+        let body = b"preamble\r\n--XX\r\n\
+            Content-Disposition: form-data; name=\"a\"\r\n\r\n\
+            one\r\n--XY not a delimiter\r\n\
+            --XX  \r\n\
+            Content-Disposition: form-data; name=\"b\"; filename=\"b.txt\"\r\n\
+            Content-Type: text/plain\r\n\r\n\
+            two\r\n\
+            --XX--\r\nepilogue";
+        let parts = read_parts(body, "XX").unwrap();
+        assert_eq!(
+            parts,
+            vec![
+                (None, b"one\r\n--XY not a delimiter".to_vec()),
+                (Some("b.txt".to_string()), b"two".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parts_larger_than_the_read_buffer() {
+        let data = vec![b'-'; READ_SIZE * 3 + 5];
+        let mut body = b"--XX\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n".to_vec();
+        body.extend_from_slice(&data);
+        body.extend_from_slice(b"\r\n--XX--\r\n");
+        assert_eq!(read_parts(&body, "XX").unwrap(), vec![(None, data)]);
+    }
+
+    #[test]
+    fn part_fields() {
+        let body = b"--XX\r\n\
+            Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+            hello\r\n\
+            --XX\r\n\
+            Content-Disposition: form-data; name=\"upload\"; filename=\"a.csv\"\r\n\
+            Content-Type: text/csv\r\n\
+            Content-Digest: sha-256=:x:\r\n\r\n\
+            a,b\r\n\
+            --XX--\r\n";
+        let mut multipart = Multipart::new(&body[..], "XX");
+
+        let part = multipart.next_part().unwrap().unwrap();
+        assert_eq!(part.name.as_deref(), Some("title"));
+        assert_eq!(part.filename, None);
+        assert_eq!(part.content_type, None);
+        assert!(!part.is_file());
+
+        let part = multipart.next_part().unwrap().unwrap();
+        assert_eq!(part.name.as_deref(), Some("upload"));
+        assert_eq!(part.filename.as_deref(), Some("a.csv"));
+        assert_eq!(part.content_type.as_deref(), Some("text/csv"));
+        assert!(part.is_file());
+        assert_eq!(
+            part.headers.get(&Header::ContentDigest).map(String::as_str),
+            Some("sha-256=:x:")
+        );
+
+        assert!(multipart.next_part().unwrap().is_none());
+    }
+
+    #[test]
+    fn quoted_filenames() {
+        let params = parse_disposition(r#"form-data; name="file"; filename="a \"b\"; c.txt""#);
+        assert_eq!(params.get("name").map(String::as_str), Some("file"));
+        assert_eq!(params.get("filename").map(String::as_str), Some("a \"b\"; c.txt"));
+
+        let params = parse_disposition(
+            "form-data; filename=\"fallback.txt\"; filename*=UTF-8''%E2%82%AC%20rates.txt",
+        );
+        assert_eq!(params.get("filename").map(String::as_str), Some("€ rates.txt"));
+
+        let params = parse_disposition("form-data; name=plain ; filename=x.bin");
+        assert_eq!(params.get("name").map(String::as_str), Some("plain"));
+        assert_eq!(params.get("filename").map(String::as_str), Some("x.bin"));
+    }
+
+    #[test]
+    fn truncated_bodies_fail() {
+        let cases: [&[u8]; 3] = [
+            b"--XX\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nno close",
+            b"--XX\r\nContent-Disposition: form-data; name=\"a\"",
+            b"no delimiter at all",
+        ];
+        for body in cases {
+            let error = read_parts(body, "XX").unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+        }
+    }
+}
@@ -0,0 +1,337 @@
+use crate::regex::Regex;
+use crate::url;
+
+pub type Params = Vec<(String, String)>;
+
+// A route path, parsed once when the route is added
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    path: String,
+    // None for a path without parameters, which matches the targets it prefixes
+    segments: Option<Vec<Segment>>,
+}
+
+#[derive(Clone, Debug)]
+pub enum Segment {
+    Literal(String),
+    // `{name}`, or `{name:constraint}` for values the constraint accepts only
+    Param {
+        name: String,
+        constraint: Option<Constraint>,
+    },
+    // `{*name}`, the rest of the target
+    Wildcard(String),
+}
+
+// What a parameter's value must look like: a number type such as `u32`,
+// or else a pattern the whole value must match, e.g. `[0-9a-f]{64}`
+#[derive(Clone, Debug)]
+pub struct Constraint {
+    check: Check,
+    shape: Shape,
+}
+
+#[derive(Clone, Debug)]
+enum Check {
+    Type(fn(&str) -> bool),
+    Regex(Regex),
+}
+
+// Loosely, what the values a constraint accepts look like: how many
+// characters they may have, and which characters may appear in them (None
+// for any). Constraints whose shapes don't overlap never accept the same value.
+#[derive(Clone, Debug)]
+struct Shape {
+    lengths: (usize, usize),
+    chars: Option<Vec<(char, char)>>,
+}
+
+// Numbers may have a sign and any number of leading zeros
+const UNSIGNED: &[(char, char)] = &[('+', '+'), ('0', '9')];
+const SIGNED: &[(char, char)] = &[('+', '+'), ('-', '-'), ('0', '9')];
+const BOOL: &[(char, char)] = &[('a', 'a'), ('e', 'f'), ('l', 'l'), ('r', 'u')];
+
+impl Constraint {
+    pub fn parse(source: &str) -> Result<Constraint, String> {
+        let check: Option<fn(&str) -> bool> = match source {
+            "u8" => Some(|value| value.parse::<u8>().is_ok()),
+            "u16" => Some(|value| value.parse::<u16>().is_ok()),
+            "u32" => Some(|value| value.parse::<u32>().is_ok()),
+            "u64" => Some(|value| value.parse::<u64>().is_ok()),
+            "usize" => Some(|value| value.parse::<usize>().is_ok()),
+            "i32" => Some(|value| value.parse::<i32>().is_ok()),
+            "i64" => Some(|value| value.parse::<i64>().is_ok()),
+            "bool" => Some(|value| value.parse::<bool>().is_ok()),
+            _ => None,
+        };
+        if let Some(check) = check {
+            let (lengths, chars) = match source {
+                "bool" => ((4, 5), BOOL),
+                "i32" | "i64" => ((1, usize::MAX), SIGNED),
+                _ => ((1, usize::MAX), UNSIGNED),
+            };
+            return Ok(Constraint {
+                check: Check::Type(check),
+                shape: Shape {
+                    lengths,
+                    chars: Some(chars.to_vec()),
+                },
+            });
+        }
+        let regex = Regex::parse(source)?;
+        Ok(Constraint {
+            shape: Shape {
+                lengths: regex.lengths(),
+                chars: regex.chars(),
+            },
+            check: Check::Regex(regex),
+        })
+    }
+
+    pub fn accepts(&self, value: &str) -> bool {
+        match &self.check {
+            Check::Type(check) => check(value),
+            Check::Regex(regex) => regex.is_match(value),
+        }
+    }
+
+    // Whether no value can satisfy both constraints. False when that can't
+    // be told cheaply, so overlapping constraints are never missed.
+    pub fn is_disjoint(&self, other: &Constraint) -> bool {
+        let (a, b) = (&self.shape, &other.shape);
+        let lengths_overlap = a.lengths.0 <= b.lengths.1 && b.lengths.0 <= a.lengths.1;
+        let chars_overlap = match (&a.chars, &b.chars) {
+            (Some(a), Some(b)) => a
+                .iter()
+                .any(|(low, high)| b.iter().any(|(l, h)| low <= h && l <= high)),
+            _ => true,
+        };
+        // Parameter values are never empty, so a common length alone isn't
+        // a common value
+        !(lengths_overlap && chars_overlap)
+    }
+}
+
+impl Pattern {
+    pub fn parse(path: &str) -> Result<Pattern, String> {
+        if !path.contains('{') {
+            return Ok(Pattern {
+                path: path.to_string(),
+                segments: None,
+            });
+        }
+
+        let parts = path.trim_start_matches('/').split('/').collect::<Vec<_>>();
+        let mut segments = Vec::new();
+        for (i, part) in parts.iter().enumerate() {
+            let param = match part
+                .strip_prefix('{')
+                .and_then(|part| part.strip_suffix('}'))
+            {
+                Some(param) => param,
+                None if part.contains('{') => {
+                    return Err(format!("{} must be a whole segment", part));
+                }
+                None => {
+                    segments.push(Segment::Literal(part.to_string()));
+                    continue;
+                }
+            };
+            let (name, constraint) = match param.split_once(':') {
+                Some((name, constraint)) => (name, Some(constraint)),
+                None => (param, None),
+            };
+            let segment = match name.strip_prefix('*') {
+                Some(_) if constraint.is_some() => {
+                    return Err(format!("{{{}}} can't have a constraint", name));
+                }
+                Some(_) if i + 1 < parts.len() => {
+                    return Err(format!("{{{}}} must be the last segment", name));
+                }
+                Some(name) => Segment::Wildcard(name.to_string()),
+                None => Segment::Param {
+                    name: name.to_string(),
+                    constraint: constraint.map(Constraint::parse).transpose()?,
+                },
+            };
+            if let Segment::Param { name, .. } | Segment::Wildcard(name) = &segment {
+                if name.is_empty() {
+                    return Err(format!("{} has no parameter name", part));
+                }
+                if segments.iter().any(|other| other.name() == Some(name)) {
+                    return Err(format!("{} is used twice", name));
+                }
+            }
+            segments.push(segment);
+        }
+        Ok(Pattern {
+            path: path.to_string(),
+            segments: Some(segments),
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn segments(&self) -> Option<&[Segment]> {
+        self.segments.as_deref()
+    }
+
+    // The values the pattern captures from a target, if it matches. A value
+    // its constraint refuses means no match.
+    pub fn matches(&self, target: &str) -> Option<Params> {
+        let segments = match &self.segments {
+            Some(segments) => segments,
+            None => {
+                let key = &self.path;
+                let matches_root = key == "/" && target == "/";
+                let matches_prefix = target.starts_with(key.as_str()) && key != "/";
+                return (matches_root || matches_prefix).then(Vec::new);
+            }
+        };
+
+        let path = target.split_once('?').map_or(target, |(path, _)| path);
+        let mut parts = path.strip_prefix('/')?.split('/');
+        let mut params = Vec::new();
+        for segment in segments {
+            let part = match segment {
+                Segment::Wildcard(name) => {
+                    let rest = parts.by_ref().collect::<Vec<_>>().join("/");
+                    params.push((name.clone(), url::percent_decode(&rest).ok()?));
+                    return Some(params);
+                }
+                _ => parts.next()?,
+            };
+            match segment {
+                Segment::Literal(literal) if literal != part => return None,
+                Segment::Param { .. } if part.is_empty() => return None,
+                Segment::Param { name, constraint } => {
+                    let value = url::percent_decode(part).ok()?;
+                    if !constraint.as_ref().is_none_or(|c| c.accepts(&value)) {
+                        return None;
+                    }
+                    params.push((name.clone(), value));
+                }
+                _ => {}
+            }
+        }
+        parts.next().is_none().then_some(params)
+    }
+
+    // Orders patterns that match the same target, most specific last: segment
+    // by segment, a literal beats a constrained parameter, which beats a plain
+    // one, which beats a wildcard. Patterns that would tie conflict, so only
+    // prefix paths are told apart by length, the longer one winning.
+    pub fn specificity(&self) -> (Vec<u8>, usize) {
+        let ranks = match &self.segments {
+            Some(segments) => segments.iter().map(Segment::rank).collect(),
+            None => vec![3; self.path.split('/').filter(|part| !part.is_empty()).count()],
+        };
+        (ranks, self.path.len())
+    }
+
+    // Whether some target may match both patterns with neither more specific
+    // than the other, so which one it goes to couldn't be decided. Two
+    // constrained parameters in the same place conflict unless their
+    // constraints are disjoint.
+    pub fn conflicts_with(&self, other: &Pattern) -> bool {
+        let (segments, others) = match (&self.segments, &other.segments) {
+            (Some(segments), Some(others)) => (segments, others),
+            _ => return false,
+        };
+        self.specificity().0 == other.specificity().0
+            && segments.iter().zip(others).all(|pair| match pair {
+                (Segment::Literal(a), Segment::Literal(b)) => a == b,
+                (
+                    Segment::Param {
+                        constraint: Some(a),
+                        ..
+                    },
+                    Segment::Param {
+                        constraint: Some(b),
+                        ..
+                    },
+                ) => !a.is_disjoint(b),
+                // Equal ranks make both of these plain or wildcards
+                _ => true,
+            })
+    }
+}
+
+impl Segment {
+    fn name(&self) -> Option<&String> {
+        match self {
+            Segment::Literal(_) => None,
+            Segment::Param { name, .. } | Segment::Wildcard(name) => Some(name),
+        }
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            Segment::Literal(_) => 3,
+            Segment::Param {
+                constraint: Some(_),
+                ..
+            } => 2,
+            Segment::Param { .. } => 1,
+            Segment::Wildcard(_) => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Pattern;
+
+    fn pattern(path: &str) -> Pattern {
+        Pattern::parse(path).unwrap()
+    }
+
+    fn conflict(a: &str, b: &str) -> bool {
+        pattern(a).conflicts_with(&pattern(b))
+    }
+
+    #[test]
+    fn constraints_filter_values() {
+        let hex = pattern("/files/{id:[0-9a-f]{64}}");
+        let digest = "ab".repeat(32);
+        let params = hex.matches(&format!("/files/{}", digest)).unwrap();
+        assert_eq!(params, vec![("id".to_string(), digest)]);
+        assert!(hex.matches("/files/abc").is_none());
+
+        let number = pattern("/n/{n:u32}");
+        assert!(number.matches("/n/4294967295").is_some());
+        assert!(number.matches("/n/4294967296").is_none());
+        assert!(number.matches("/n/x").is_none());
+    }
+
+    #[test]
+    fn overlapping_constraints_conflict() {
+        assert!(conflict("/x/{n:u32}", "/x/{id:[0-9]+}"));
+        assert!(conflict("/x/{a:u32}", "/x/{b:u32}"));
+        assert!(conflict("/x/{a:u8}", "/x/{b:i64}"));
+        assert!(conflict("/x/{a}", "/x/{b}"));
+        assert!(conflict("/x/{*a}", "/x/{*b}"));
+        assert!(conflict("/x/{a:.+}", "/x/{b:u32}"));
+    }
+
+    #[test]
+    fn disjoint_or_ranked_patterns_dont_conflict() {
+        assert!(!conflict("/x/{n:u32}", "/x/{name:[a-z]+}"));
+        assert!(!conflict("/x/{n:[0-9]{3}}", "/x/{m:[0-9]{4}}"));
+        assert!(!conflict("/x/{b:bool}", "/x/{n:u32}"));
+        assert!(!conflict("/x/{n:u32}", "/y/{n:u32}"));
+        // A constrained parameter is more specific than a plain one
+        assert!(!conflict("/x/{n:u32}", "/x/{name}"));
+        assert!(!conflict("/x/{a}/y", "/x/{a}/{b}"));
+    }
+
+    #[test]
+    fn more_specific_patterns_rank_higher() {
+        let ranked = ["/x/{*rest}", "/x/{name}", "/x/{n:u32}", "/x/5"];
+        for pair in ranked.windows(2) {
+            assert!(pattern(pair[0]).specificity() < pattern(pair[1]).specificity());
+        }
+    }
+}
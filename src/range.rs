@@ -0,0 +1,246 @@
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Requests asking for more ranges than this are served in full instead
+const MAX_RANGES: usize = 32;
+
+// An inclusive byte span within a representation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    // Inclusive, so never empty
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    pub fn content_range(&self, complete_length: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, complete_length)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RangeError {
+    // Not a byte range we understand; the header must be ignored
+    Invalid,
+    // Well-formed, but no range overlaps the representation
+    Unsatisfiable,
+}
+
+// Parses a `Range: bytes=...` value against a representation of `len` bytes.
+// Unsatisfiable specs are dropped, overlapping ones are coalesced, and the
+// result is sorted by offset.
+pub fn parse_range(value: &str, len: u64) -> Result<Vec<ByteRange>, RangeError> {
+    let (unit, specs) = value.split_once('=').ok_or(RangeError::Invalid)?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return Err(RangeError::Invalid);
+    }
+
+    let mut ranges = Vec::new();
+    let mut count = 0;
+    for spec in specs.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+        count += 1;
+        if count > MAX_RANGES {
+            return Err(RangeError::Invalid);
+        }
+
+        let (first, last) = spec.split_once('-').ok_or(RangeError::Invalid)?;
+        let (first, last) = (first.trim(), last.trim());
+        let range = if first.is_empty() {
+            // Suffix range: the last N bytes
+            let suffix: u64 = last.parse().map_err(|_| RangeError::Invalid)?;
+            if suffix == 0 || len == 0 {
+                continue;
+            }
+            ByteRange {
+                start: len.saturating_sub(suffix),
+                end: len - 1,
+            }
+        } else {
+            let start: u64 = first.parse().map_err(|_| RangeError::Invalid)?;
+            let end = if last.is_empty() {
+                u64::MAX
+            } else {
+                last.parse().map_err(|_| RangeError::Invalid)?
+            };
+            if end < start {
+                return Err(RangeError::Invalid);
+            }
+            if start >= len {
+                continue;
+            }
+            ByteRange {
+                start,
+                end: end.min(len - 1),
+            }
+        };
+        ranges.push(range);
+    }
+
+    if count == 0 {
+        return Err(RangeError::Invalid);
+    }
+    if ranges.is_empty() {
+        return Err(RangeError::Unsatisfiable);
+    }
+
+    ranges.sort_by_key(|range| range.start);
+    let mut coalesced: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match coalesced.last_mut() {
+            Some(previous) if range.start <= previous.end.saturating_add(1) => {
+                previous.end = previous.end.max(range.end);
+            }
+            _ => coalesced.push(range),
+        }
+    }
+    Ok(coalesced)
+}
+
+pub fn multipart_boundary() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    format!("{:x}{:x}", nanos, std::process::id())
+}
+
+// The headers opening one part of a multipart/byteranges body
+fn part_head(range: &ByteRange, boundary: &str, content_type: &str, total: u64) -> String {
+    format!(
+        "--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+        boundary,
+        content_type,
+        range.content_range(total)
+    )
+}
+
+// The length of the body `write_multipart_byteranges` writes, known before
+// any of the file is read
+pub fn multipart_byteranges_len(
+    ranges: &[ByteRange],
+    boundary: &str,
+    content_type: &str,
+    complete_length: u64,
+) -> u64 {
+    let parts: u64 = ranges
+        .iter()
+        .map(|range| {
+            let head = part_head(range, boundary, content_type, complete_length);
+            head.len() as u64 + range.len() + 2
+        })
+        .sum();
+    parts + format!("--{}--\r\n", boundary).len() as u64
+}
+
+// Writes a multipart/byteranges body, with `write_range` writing the bytes of
+// each range in turn
+pub fn write_multipart_byteranges(
+    out: &mut dyn Write,
+    ranges: &[ByteRange],
+    boundary: &str,
+    content_type: &str,
+    complete_length: u64,
+    mut write_range: impl FnMut(&ByteRange, &mut dyn Write) -> io::Result<()>,
+) -> io::Result<()> {
+    for range in ranges {
+        let head = part_head(range, boundary, content_type, complete_length);
+        out.write_all(head.as_bytes())?;
+        write_range(range, out)?;
+        out.write_all(b"\r\n")?;
+    }
+    write!(out, "--{}--\r\n", boundary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: u64, end: u64) -> ByteRange {
+        ByteRange { start, end }
+    }
+
+    #[test]
+    fn single_ranges() {
+        assert_eq!(parse_range("bytes=0-9", 100), Ok(vec![range(0, 9)]));
+        assert_eq!(parse_range("bytes=90-", 100), Ok(vec![range(90, 99)]));
+        // The end is clamped to the representation
+        assert_eq!(parse_range("bytes=50-1000", 100), Ok(vec![range(50, 99)]));
+        assert_eq!(parse_range(" Bytes = 1-1 ", 100), Ok(vec![range(1, 1)]));
+    }
+
+    #[test]
+    fn suffix_ranges() {
+        assert_eq!(parse_range("bytes=-10", 100), Ok(vec![range(90, 99)]));
+        assert_eq!(parse_range("bytes=-1000", 100), Ok(vec![range(0, 99)]));
+        assert_eq!(parse_range("bytes=-0", 100), Err(RangeError::Unsatisfiable));
+        assert_eq!(parse_range("bytes=-5", 0), Err(RangeError::Unsatisfiable));
+    }
+
+    #[test]
+    fn multiple_ranges_are_sorted_and_coalesced() {
+        assert_eq!(
+            parse_range("bytes=50-59, 0-9, 5-14, 15-19, -10", 100),
+            Ok(vec![range(0, 19), range(50, 59), range(90, 99)])
+        );
+        // Unsatisfiable specs among satisfiable ones are dropped
+        assert_eq!(
+            parse_range("bytes=200-300, 10-20", 100),
+            Ok(vec![range(10, 20)])
+        );
+        assert_eq!(parse_range("bytes=0-,-1", 100), Ok(vec![range(0, 99)]));
+    }
+
+    #[test]
+    fn unsatisfiable_ranges() {
+        assert_eq!(parse_range("bytes=100-", 100), Err(RangeError::Unsatisfiable));
+        assert_eq!(parse_range("bytes=100-200, 150-", 100), Err(RangeError::Unsatisfiable));
+        assert_eq!(parse_range("bytes=0-0", 0), Err(RangeError::Unsatisfiable));
+    }
+
+    #[test]
+    fn invalid_ranges() {
+        for value in [
+            "items=0-1",
+            "bytes",
+            "bytes=",
+            "bytes=5",
+            "bytes=5-4",
+            "bytes=a-b",
+            "bytes=--5",
+            "bytes=0-1, x",
+        ] {
+            assert_eq!(parse_range(value, 100), Err(RangeError::Invalid), "{}", value);
+        }
+        let many = vec!["0-0"; MAX_RANGES + 1].join(",");
+        assert_eq!(parse_range(&format!("bytes={}", many), 100), Err(RangeError::Invalid));
+        let most = vec!["0-0"; MAX_RANGES].join(",");
+        assert!(parse_range(&format!("bytes={}", most), 100).is_ok());
+    }
+
+    #[test]
+    fn multipart_body_matches_its_length() {
+        let data: Vec<u8> = (0..100).collect();
+        let ranges = [range(0, 9), range(90, 99)];
+        let mut body = Vec::new();
+        write_multipart_byteranges(&mut body, &ranges, "XX", "text/plain", 100, |range, out| {
+            out.write_all(&data[range.start as usize..=range.end as usize])
+        })
+        .unwrap();
+        assert_eq!(body.len() as u64, multipart_byteranges_len(&ranges, "XX", "text/plain", 100));
+
+        let mut expected =
+            b"--XX\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-9/100\r\n\r\n".to_vec();
+        expected.extend_from_slice(&data[..10]);
+        expected.extend_from_slice(
+            b"\r\n--XX\r\nContent-Type: text/plain\r\nContent-Range: bytes 90-99/100\r\n\r\n",
+        );
+        expected.extend_from_slice(&data[90..]);
+        expected.extend_from_slice(b"\r\n--XX--\r\n");
+        assert_eq!(body, expected);
+    }
+}
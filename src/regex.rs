@@ -0,0 +1,343 @@
+// A small subset of regular expressions, enough to constrain route
+// parameters: literals, `.`, classes like `[0-9a-f]` or `[^/]`, the escapes
+// `\d`, `\w` and `\s`, and the quantifiers `*`, `+`, `?`, `{n}`, `{n,}` and
+// `{n,m}`. Groups and alternation aren't supported. A pattern always matches
+// the whole text, so `^` and `$` at its ends change nothing.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Regex {
+    items: Vec<Item>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Item {
+    atom: Atom,
+    min: usize,
+    max: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Atom {
+    Char(char),
+    Any,
+    Class {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+}
+
+impl Atom {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Atom::Char(expected) => *expected == c,
+            Atom::Any => true,
+            Atom::Class { ranges, negated } => {
+                ranges.iter().any(|(low, high)| (*low..=*high).contains(&c)) != *negated
+            }
+        }
+    }
+}
+
+const DIGIT: &[(char, char)] = &[('0', '9')];
+const WORD: &[(char, char)] = &[('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')];
+const SPACE: &[(char, char)] = &[(' ', ' '), ('\t', '\r')];
+
+impl Regex {
+    pub fn parse(pattern: &str) -> Result<Regex, String> {
+        let pattern = pattern.strip_prefix('^').unwrap_or(pattern);
+        let pattern = match pattern.strip_suffix('$') {
+            Some(rest) if !rest.ends_with('\\') => rest,
+            _ => pattern,
+        };
+        let chars = pattern.chars().collect::<Vec<_>>();
+        let mut pos = 0;
+        let mut items = Vec::new();
+        while pos < chars.len() {
+            let c = chars[pos];
+            pos += 1;
+            let atom = match c {
+                '.' => Atom::Any,
+                '[' => parse_class(&chars, &mut pos)?,
+                '\\' => {
+                    let escaped = *chars.get(pos).ok_or("trailing backslash")?;
+                    pos += 1;
+                    parse_escape(escaped)
+                }
+                '*' | '+' | '?' | '{' => return Err(format!("nothing to repeat before {}", c)),
+                '(' | ')' | '|' | '^' | '$' => return Err(format!("{} is not supported", c)),
+                c => Atom::Char(c),
+            };
+            let (min, max) = parse_quantifier(&chars, &mut pos)?;
+            items.push(Item { atom, min, max });
+        }
+        Ok(Regex { items })
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        let text = text.chars().collect::<Vec<_>>();
+        let mut matcher = Matcher {
+            items: &self.items,
+            failed: vec![false; (self.items.len() + 1) * (text.len() + 1)],
+            text: &text,
+        };
+        matcher.matches_from(0, 0)
+    }
+
+    // The fewest and most characters a match can have
+    pub fn lengths(&self) -> (usize, usize) {
+        self.items.iter().fold((0, 0), |(min, max), item| {
+            (min.saturating_add(item.min), max.saturating_add(item.max))
+        })
+    }
+
+    // Every character a match may contain, or None when that's any
+    pub fn chars(&self) -> Option<Vec<(char, char)>> {
+        let mut chars = Vec::new();
+        for item in self.items.iter().filter(|item| item.max > 0) {
+            match &item.atom {
+                Atom::Char(c) => chars.push((*c, *c)),
+                Atom::Class {
+                    ranges,
+                    negated: false,
+                } => chars.extend(ranges),
+                _ => return None,
+            }
+        }
+        Some(chars)
+    }
+}
+
+struct Matcher<'a> {
+    items: &'a [Item],
+    text: &'a [char],
+    // The (item, position) pairs known not to lead to a match, so none is
+    // tried twice and `.*.*.*` stays polynomial
+    failed: Vec<bool>,
+}
+
+impl Matcher<'_> {
+    // Tries the longest run of an item first, backing off one character at a
+    // time until the rest matches
+    fn matches_from(&mut self, item: usize, pos: usize) -> bool {
+        if item == self.items.len() {
+            return pos == self.text.len();
+        }
+        let key = item * (self.text.len() + 1) + pos;
+        if self.failed[key] {
+            return false;
+        }
+        let Item { atom, min, max } = &self.items[item];
+        let mut count = 0;
+        while count < *max && pos + count < self.text.len() && atom.matches(self.text[pos + count])
+        {
+            count += 1;
+        }
+        let matched = count >= *min
+            && (*min..=count)
+                .rev()
+                .any(|n| self.matches_from(item + 1, pos + n));
+        if !matched {
+            self.failed[key] = true;
+        }
+        matched
+    }
+}
+
+fn parse_escape(c: char) -> Atom {
+    let (ranges, negated) = match c {
+        'd' => (DIGIT, false),
+        'D' => (DIGIT, true),
+        'w' => (WORD, false),
+        'W' => (WORD, true),
+        's' => (SPACE, false),
+        'S' => (SPACE, true),
+        c => return Atom::Char(c),
+    };
+    Atom::Class {
+        ranges: ranges.to_vec(),
+        negated,
+    }
+}
+
+// Parses a class after its `[`. A `]` right after the `[` or `[^` is taken
+// literally, as is a `-` at either end.
+fn parse_class(chars: &[char], pos: &mut usize) -> Result<Atom, String> {
+    let negated = chars.get(*pos) == Some(&'^');
+    if negated {
+        *pos += 1;
+    }
+    let mut ranges = Vec::new();
+    let start = *pos;
+    loop {
+        let c = *chars.get(*pos).ok_or("unclosed [")?;
+        *pos += 1;
+        let low = match c {
+            ']' if *pos - 1 > start => return Ok(Atom::Class { ranges, negated }),
+            '\\' => {
+                let escaped = *chars.get(*pos).ok_or("trailing backslash")?;
+                *pos += 1;
+                match parse_escape(escaped) {
+                    Atom::Char(c) => c,
+                    Atom::Class {
+                        ranges: class,
+                        negated: false,
+                    } => {
+                        ranges.extend(class);
+                        continue;
+                    }
+                    _ => return Err(format!("\\{} is not supported in a class", escaped)),
+                }
+            }
+            c => c,
+        };
+        let high = match (chars.get(*pos), chars.get(*pos + 1)) {
+            (Some('-'), Some(&high)) if high != ']' => {
+                *pos += 2;
+                high
+            }
+            _ => low,
+        };
+        if high < low {
+            return Err(format!("invalid range {}-{}", low, high));
+        }
+        ranges.push((low, high));
+    }
+}
+
+fn parse_quantifier(chars: &[char], pos: &mut usize) -> Result<(usize, usize), String> {
+    let (min, max) = match chars.get(*pos) {
+        Some('*') => (0, usize::MAX),
+        Some('+') => (1, usize::MAX),
+        Some('?') => (0, 1),
+        Some('{') => {
+            let end = chars[*pos..]
+                .iter()
+                .position(|c| *c == '}')
+                .ok_or("unclosed {")?;
+            let bounds = chars[*pos + 1..*pos + end].iter().collect::<String>();
+            *pos += end;
+            let number = |s: &str| {
+                s.parse::<usize>()
+                    .map_err(|_| format!("invalid repetition {{{}}}", bounds))
+            };
+            match bounds.split_once(',') {
+                None => (number(&bounds)?, number(&bounds)?),
+                Some((min, "")) => (number(min)?, usize::MAX),
+                Some((min, max)) => (number(min)?, number(max)?),
+            }
+        }
+        _ => return Ok((1, 1)),
+    };
+    *pos += 1;
+    if min > max {
+        return Err(format!("invalid repetition {{{},{}}}", min, max));
+    }
+    if matches!(chars.get(*pos), Some('*' | '+' | '?' | '{')) {
+        return Err("nothing to repeat before a quantifier".to_string());
+    }
+    Ok((min, max))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Regex;
+    use std::time::{Duration, Instant};
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        Regex::parse(pattern).unwrap().is_match(text)
+    }
+
+    #[test]
+    fn literals_match_the_whole_text() {
+        assert!(matches("abc", "abc"));
+        assert!(!matches("abc", "abcd"));
+        assert!(!matches("abc", "xabc"));
+        assert!(matches("a.c", "a-c"));
+        assert!(matches("", ""));
+        assert!(!matches("", "a"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(matches("[0-9a-f]+", "c0ffee"));
+        assert!(!matches("[0-9a-f]+", "coffee"));
+        assert!(matches("[^/]+", "a.b"));
+        assert!(!matches("[^/]+", "a/b"));
+        // `]` first and `-` last are literal
+        assert!(matches("[]a]+", "]a]"));
+        assert!(matches("[a-]+", "a-a"));
+        assert!(matches("[\\d_]+", "1_2"));
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(matches("\\d+", "0123"));
+        assert!(!matches("\\d+", "12a"));
+        assert!(matches("\\D", "a"));
+        assert!(matches("\\w+", "snake_case9"));
+        assert!(!matches("\\w+", "kebab-case"));
+        assert!(matches("\\W", "-"));
+        assert!(matches("a\\sb", "a\tb"));
+        assert!(matches("\\S+", "x"));
+        assert!(matches("v\\.1", "v.1"));
+        assert!(!matches("v\\.1", "vx1"));
+        assert!(matches("\\*", "*"));
+    }
+
+    #[test]
+    fn repetitions() {
+        assert!(matches("[0-9]{3}", "123"));
+        assert!(!matches("[0-9]{3}", "12"));
+        assert!(!matches("[0-9]{3}", "1234"));
+        assert!(matches("a{2,3}", "aa"));
+        assert!(matches("a{2,3}", "aaa"));
+        assert!(!matches("a{2,3}", "aaaa"));
+        assert!(matches("a{2,}", "aaaaaa"));
+        assert!(!matches("a{2,}", "a"));
+        assert!(matches("ab?c", "ac"));
+        assert!(matches("ab*c", "abbbc"));
+        assert!(!matches("ab+c", "ac"));
+        // Backtracking gives characters back to what follows
+        assert!(matches("a*ab", "aaab"));
+        assert!(matches(".*-.*", "a-b-c"));
+    }
+
+    #[test]
+    fn anchors_change_nothing() {
+        assert!(matches("^abc$", "abc"));
+        assert!(!matches("^abc$", "abcd"));
+        assert!(matches("^[a-z]+", "abc"));
+        // An escaped `$` is a literal
+        assert!(matches("a\\$", "a$"));
+    }
+
+    #[test]
+    fn rejects_unsupported_syntax() {
+        for pattern in [
+            "(a)", "a|b", "*a", "a**", "a+?", "[a-", "[z-a]", "a{2", "a{x}", "a{3,2}", "\\", "a^b",
+            "[\\D]",
+        ] {
+            assert!(
+                Regex::parse(pattern).is_err(),
+                "{} should be rejected",
+                pattern
+            );
+        }
+    }
+
+    #[test]
+    fn nested_repetitions_stay_fast() {
+        let text = "a".repeat(200);
+        let start = Instant::now();
+        assert!(!matches(".*.*.*.*.*b", &text));
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn shape() {
+        let regex = Regex::parse("[0-9a-f]{64}").unwrap();
+        assert_eq!(regex.lengths(), (64, 64));
+        assert_eq!(regex.chars(), Some(vec![('0', '9'), ('a', 'f')]));
+        assert_eq!(Regex::parse("a+").unwrap().lengths(), (1, usize::MAX));
+        assert_eq!(Regex::parse("[^/]").unwrap().chars(), None);
+    }
+}
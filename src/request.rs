@@ -1,18 +1,160 @@
-use crate::http::{Header, RequestMethod};
+use crate::body::Body;
+use crate::form::FormData;
+use crate::http::{ContentType, Header, RequestMethod, Status, StatusCode};
+use crate::json::{FromJson, JsonValue};
+use crate::multipart::{self, Multipart};
+use crate::response::Response;
+use crate::url::parse_query;
 use std::collections::HashMap;
-use std::io::{BufRead, BufReader, Error, ErrorKind};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read};
 use std::net::TcpStream;
-use std::io::Read;
+
+// Bodies parsed as a whole (forms, JSON) are capped at this size
+const MAX_BUFFERED_BODY: u64 = 1024 * 1024;
 
 pub struct Request {
     pub method: RequestMethod,
     pub target: String,
     pub version: String,
-    pub body: Option<String>,
+    pub body: Body,
     pub headers: HashMap<Header, String>,
+    // Values of the `{name}` segments of the route that matched, in order
+    pub params: Vec<(String, String)>,
 }
 
 impl Request {
+    // The request target without its query string
+    pub fn path(&self) -> &str {
+        match self.target.split_once('?') {
+            Some((path, _)) => path,
+            None => &self.target,
+        }
+    }
+
+    pub fn query(&self) -> Option<&str> {
+        self.target.split_once('?').map(|(_, query)| query)
+    }
+
+    // Starts streaming a multipart/form-data body. Fails if the request isn't
+    // multipart or carries no boundary.
+    pub fn multipart(&mut self) -> Result<Multipart<&mut Body>, Error> {
+        let boundary = self
+            .headers
+            .get(&Header::ContentType)
+            .and_then(|content_type| multipart::boundary(content_type))
+            .ok_or_else(|| {
+                Error::new(ErrorKind::InvalidInput, "Not a multipart request.".to_string())
+            })?;
+        Ok(Multipart::new(&mut self.body, &boundary))
+    }
+
+    pub fn is_multipart(&self) -> bool {
+        self.headers
+            .get(&Header::ContentType)
+            .map(|content_type| content_type.to_lowercase().starts_with("multipart/form-data"))
+            .unwrap_or(false)
+    }
+
+    // Reads and parses an application/x-www-form-urlencoded body
+    pub fn form(&mut self) -> Result<FormData, Response> {
+        self.expect_content_type(|media_type| media_type == "application/x-www-form-urlencoded")?;
+        let body = self.read_body_to_string()?;
+        Ok(FormData::parse(&body))
+    }
+
+    // Reads a JSON body and converts it into `T`
+    pub fn json<T: FromJson>(&mut self) -> Result<T, Response> {
+        self.expect_content_type(|media_type| {
+            media_type == "application/json" || media_type.ends_with("+json")
+        })?;
+        let body = self.read_body_to_string()?;
+        let value = JsonValue::parse(&body)
+            .map_err(|e| client_error(StatusCode::BadRequest, format!("Malformed JSON body: {}", e)))?;
+        T::from_json(&value)
+            .map_err(|e| client_error(StatusCode::BadRequest, format!("Invalid JSON body: {}", e)))
+    }
+
+    fn expect_content_type(&self, accepts: impl Fn(&str) -> bool) -> Result<(), Response> {
+        let media_type = self
+            .headers
+            .get(&Header::ContentType)
+            .map(|content_type| {
+                content_type
+                    .split(';')
+                    .next()
+                    .unwrap_or_default()
+                    .trim()
+                    .to_lowercase()
+            })
+            .unwrap_or_default();
+        if accepts(&media_type) {
+            return Ok(());
+        }
+        let message = if media_type.is_empty() {
+            "Missing Content-Type".to_string()
+        } else {
+            format!("Unsupported Content-Type: {}", media_type)
+        };
+        Err(client_error(StatusCode::UnsupportedMediaType, message))
+    }
+
+    // The whole body as text, for formats without a dedicated helper
+    pub fn text(&mut self) -> Result<String, Response> {
+        self.read_body_to_string()
+    }
+
+    // The whole body, up to the same limit as parsed bodies
+    pub fn bytes(&mut self) -> Result<Vec<u8>, Response> {
+        if self.body.remaining().unwrap_or(0) > MAX_BUFFERED_BODY {
+            return Err(client_error(
+                StatusCode::PayloadTooLarge,
+                "Request body too large".to_string(),
+            ));
+        }
+        let mut buffer = Vec::new();
+        let read = (&mut self.body)
+            .take(MAX_BUFFERED_BODY + 1)
+            .read_to_end(&mut buffer);
+        let too_large = match read {
+            Ok(_) => buffer.len() as u64 > MAX_BUFFERED_BODY,
+            // Past the server's limit, which may be lower
+            Err(e) if e.kind() == ErrorKind::FileTooLarge => true,
+            Err(e) => {
+                return Err(client_error(
+                    StatusCode::BadRequest,
+                    format!("Unreadable body: {}", e),
+                ))
+            }
+        };
+        if too_large {
+            return Err(client_error(
+                StatusCode::PayloadTooLarge,
+                "Request body too large".to_string(),
+            ));
+        }
+        Ok(buffer)
+    }
+
+    fn read_body_to_string(&mut self) -> Result<String, Response> {
+        String::from_utf8(self.bytes()?)
+            .map_err(|_| client_error(StatusCode::BadRequest, "Body is not valid UTF-8".to_string()))
+    }
+
+    // The value the matched route captured for `{name}`
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn query_param(&self, name: &str) -> Option<String> {
+        parse_query(self.query()?)
+            .into_iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value)
+    }
+
     pub fn builder(stream: &TcpStream) -> Result<Request, Error> {
         let mut buf_reader = BufReader::new(stream.try_clone().unwrap());
         let mut request_str = String::new();
@@ -47,23 +189,29 @@ impl Request {
 
             let method = RequestMethod::from_string(method)?;
 
-            // Check for Content-Length header to read the body
-            let mut body = String::new();
-            if let Some(content_length) = headers.get(&Header::ContentLength) {
-                let content_length: usize = content_length.parse().unwrap_or(0);
-                if content_length > 0 {
-                    let mut buffer = vec![0; content_length];
-                    buf_reader.read_exact(&mut buffer)?;
-                    body = String::from_utf8(buffer).unwrap_or_default();
-                }
-            }
+            // The body is left on the connection for the handler to stream
+            let chunked = headers
+                .get(&Header::TransferEncoding)
+                .map(|encoding| encoding.to_lowercase().contains("chunked"))
+                .unwrap_or(false);
+            let body = if chunked {
+                Body::chunked(Box::new(buf_reader))
+            } else if let Some(content_length) = headers.get(&Header::ContentLength) {
+                let content_length: u64 = content_length.parse().map_err(|_| {
+                    Error::new(ErrorKind::InvalidData, "Invalid Content-Length.".to_string())
+                })?;
+                Body::with_length(Box::new(buf_reader), content_length)
+            } else {
+                Body::empty()
+            };
 
             let request = Request {
                 method,
                 target: target.to_string(),
-                version: version.to_string(),
-                body: if body.is_empty() { None } else { Some(body) },
+                version: version.trim().to_string(),
+                body,
                 headers,
+                params: Vec::new(),
             };
 
             Ok(request)
@@ -74,4 +222,10 @@ impl Request {
             ))
         }
     }
-}
\ No newline at end of file
+}
+
+pub fn client_error(status: StatusCode, message: String) -> Response {
+    let mut headers = HashMap::new();
+    headers.insert(Header::ContentType, ContentType::TextPlain.to_string());
+    Response::builder(Status::new(status), message, headers)
+}
@@ -1,19 +1,47 @@
 use crate::encoding::{ContentEncoding, Encoding};
 use crate::http::{ContentType, Header, Status, StatusCode};
+use crate::json::JsonValue;
+use bytes::Bytes;
 use std::collections::{HashMap, HashSet};
-use std::io::Write;
-use std::net::TcpStream;
+use std::io::{self, BufWriter, Write};
+use std::net::{Shutdown, TcpStream};
+use std::ops::{Deref, DerefMut};
 
-pub struct Response {
+// Streamed bodies go out in chunks of about this size
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// Produces a body as it is sent, for responses too large to build in memory
+pub type StreamBody = Box<dyn FnOnce(&mut dyn Write) -> io::Result<()> + Send>;
+
+// Boxed, so that results failing with a response stay small
+pub struct Response(Box<ResponseParts>);
+
+pub struct ResponseParts {
     pub version: String,
     pub status: Status,
     pub headers: HashMap<Header, String>,
     pub body: Vec<u8>,
     pub content_encodings: HashSet<ContentEncoding>,
+    // Set for responses to HEAD: headers describe the body, but none is sent
+    pub head: bool,
+    // Set for HTTP/1.0 clients, which don't understand chunked framing: a
+    // stream without a length is sent as-is and ended by closing the connection
+    pub close_delimited: bool,
+    // Written after the head in place of `body`, with chunked framing unless
+    // the headers give its length
+    pub stream: Option<StreamBody>,
 }
 
 impl Response {
     pub fn builder(status: Status, body: String, headers: HashMap<Header, String>) -> Response {
+        Response::builder_bytes(status, body.into_bytes(), headers)
+    }
+
+    pub fn builder_bytes(
+        status: Status,
+        body: Vec<u8>,
+        headers: HashMap<Header, String>,
+    ) -> Response {
         let mut content_encodings = HashSet::new();
 
         if let Some(accept_encoding) = headers.get(&Header::AcceptEncoding) {
@@ -22,13 +50,29 @@ impl Response {
             }
         }
 
-        Response {
+        Response(Box::new(ResponseParts {
             status,
             headers,
             content_encodings,
+            head: false,
+            close_delimited: false,
+            stream: None,
             version: "HTTP/1.1".to_string(),
-            body: body.into_bytes(),
-        }
+            body,
+        }))
+    }
+
+    pub fn builder_stream<F>(
+        status: Status,
+        headers: HashMap<Header, String>,
+        body: F,
+    ) -> Response
+    where
+        F: FnOnce(&mut dyn Write) -> io::Result<()> + Send + 'static,
+    {
+        let mut response = Response::builder_bytes(status, Vec::new(), headers);
+        response.stream = Some(Box::new(body));
+        response
     }
 
     pub fn to_bytes(&self) -> Vec<u8> {
@@ -45,16 +89,41 @@ impl Response {
         let mut encoded_body = self.body.clone();
         let mut headers = self.headers.clone();
 
-        // Encode the body if gzip is present in content encodings
-        if self.content_encodings.contains(&ContentEncoding::GZIP) {
-            encoded_body = ContentEncoding::GZIP.encode(&String::from_utf8(self.body.clone()).unwrap());
-            // encoded_body = encode(&encoded_body).into();
+        // Encode the body if gzip is present in content encodings, unless the
+        // handler already chose an encoding (e.g. a precompressed file)
+        if self.stream.is_some() {
+            // A streamed body without a length the handler knows is chunked,
+            // or for HTTP/1.0 ends with the connection
+            if !headers.contains_key(&Header::ContentLength) {
+                if self.close_delimited {
+                    headers.insert(Header::Connection, "close".to_string());
+                } else {
+                    headers.insert(Header::TransferEncoding, "chunked".to_string());
+                }
+            }
+        } else if self.content_encodings.contains(&ContentEncoding::GZIP)
+            && !headers.contains_key(&Header::ContentEncoding)
+        {
+            encoded_body = ContentEncoding::GZIP.encode(&self.body);
             // Update Content-Encoding header
             headers.insert(Header::ContentEncoding, "gzip".to_string());
         }
 
-        // Update Content-Length header to reflect the encoded body length
-        headers.insert(Header::ContentLength, encoded_body.len().to_string());
+        // Update Content-Length header to reflect the encoded body length. A
+        // HEAD response keeps the length a handler reported without a body.
+        if !self.status.code.allows_body() {
+            headers.remove(&Header::ContentLength);
+            encoded_body.clear();
+        } else if self.stream.is_some() {
+            encoded_body.clear();
+        } else if self.head {
+            if !encoded_body.is_empty() {
+                headers.insert(Header::ContentLength, encoded_body.len().to_string());
+            }
+            encoded_body.clear();
+        } else {
+            headers.insert(Header::ContentLength, encoded_body.len().to_string());
+        }
 
         // Write each header
         for (key, value) in &headers {
@@ -70,10 +139,222 @@ impl Response {
         buffer
     }
 
-    pub fn send(&self, stream: &mut TcpStream) -> std::io::Result<()> {
+    pub fn send(mut self, stream: &mut TcpStream) -> std::io::Result<()> {
         let response_bytes = self.to_bytes();
         stream.write_all(&response_bytes)?;
+        let sends_body = !self.head && self.status.code.allows_body();
+        if let Some(body) = self.stream.take().filter(|_| sends_body) {
+            if self.headers.contains_key(&Header::ContentLength) || self.close_delimited {
+                // A failed stream ends short of the length, which the client
+                // can tell once the connection closes
+                let mut writer = BufWriter::with_capacity(CHUNK_SIZE, &mut *stream);
+                body(&mut writer)?;
+                writer.flush()?;
+            } else {
+                let chunked = ChunkedWriter {
+                    inner: &mut *stream,
+                };
+                let mut writer = BufWriter::with_capacity(CHUNK_SIZE, chunked);
+                body(&mut writer)?;
+                // A failed stream ends without the last chunk, so the client
+                // can tell the body is incomplete
+                writer.into_inner().map_err(|e| e.into_error())?.finish()?;
+            }
+        }
         stream.flush()?;
+        if self.close_delimited {
+            stream.shutdown(Shutdown::Write)?;
+        }
         Ok(())
     }
 }
+
+impl Deref for Response {
+    type Target = ResponseParts;
+
+    fn deref(&self) -> &ResponseParts {
+        &self.0
+    }
+}
+
+impl DerefMut for Response {
+    fn deref_mut(&mut self) -> &mut ResponseParts {
+        &mut self.0
+    }
+}
+
+// What a handler may return in place of a `Response`
+pub trait IntoResponse {
+    fn into_response(self) -> Response;
+}
+
+impl IntoResponse for Response {
+    fn into_response(self) -> Response {
+        self
+    }
+}
+
+// An empty body with the given status
+impl IntoResponse for StatusCode {
+    fn into_response(self) -> Response {
+        Response::builder(Status::new(self), String::new(), HashMap::new())
+    }
+}
+
+impl IntoResponse for &str {
+    fn into_response(self) -> Response {
+        self.to_string().into_response()
+    }
+}
+
+impl IntoResponse for String {
+    fn into_response(self) -> Response {
+        let mut headers = HashMap::new();
+        headers.insert(
+            Header::ContentType,
+            format!("{}; charset=utf-8", ContentType::TextPlain),
+        );
+        Response::builder(Status::new(StatusCode::Ok), self, headers)
+    }
+}
+
+impl IntoResponse for Vec<u8> {
+    fn into_response(self) -> Response {
+        let mut headers = HashMap::new();
+        headers.insert(
+            Header::ContentType,
+            ContentType::ApplicationOctetStream.to_string(),
+        );
+        Response::builder_bytes(Status::new(StatusCode::Ok), self, headers)
+    }
+}
+
+impl IntoResponse for Bytes {
+    fn into_response(self) -> Response {
+        self.to_vec().into_response()
+    }
+}
+
+impl IntoResponse for JsonValue {
+    fn into_response(self) -> Response {
+        let mut headers = HashMap::new();
+        headers.insert(Header::ContentType, ContentType::ApplicationJson.to_string());
+        Response::builder(Status::new(StatusCode::Ok), self.to_string(), headers)
+    }
+}
+
+impl<T: IntoResponse> IntoResponse for (StatusCode, T) {
+    fn into_response(self) -> Response {
+        let (code, body) = self;
+        let mut response = body.into_response();
+        response.status = Status::new(code);
+        response
+    }
+}
+
+// Headers given here replace those the body would set
+impl<T: IntoResponse> IntoResponse for (StatusCode, HashMap<Header, String>, T) {
+    fn into_response(self) -> Response {
+        let (code, headers, body) = self;
+        let mut response = (code, body).into_response();
+        response.headers.extend(headers);
+        response
+    }
+}
+
+impl<T: IntoResponse, E: IntoResponse> IntoResponse for Result<T, E> {
+    fn into_response(self) -> Response {
+        match self {
+            Ok(response) => response.into_response(),
+            Err(response) => response.into_response(),
+        }
+    }
+}
+
+// Frames everything written to it as one HTTP/1.1 chunk per write
+struct ChunkedWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    fn finish(&mut self) -> io::Result<()> {
+        self.inner.write_all(b"0\r\n\r\n")
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        write!(self.inner, "{:x}\r\n", buf.len())?;
+        self.inner.write_all(buf)?;
+        self.inner.write_all(b"\r\n")?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    // What a client reads for `response`, up to the server closing the connection
+    fn sent(response: Response) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        response.send(&mut server).unwrap();
+        drop(server);
+        let mut received = String::new();
+        client.read_to_string(&mut received).unwrap();
+        received
+    }
+
+    fn streamed() -> Response {
+        Response::builder_stream(Status::new(StatusCode::Ok), HashMap::new(), |out| {
+            out.write_all(b"hello, ")?;
+            out.flush()?;
+            out.write_all(b"world")
+        })
+    }
+
+    #[test]
+    fn streams_without_a_length_are_chunked_for_http_1_1() {
+        let received = sent(streamed());
+        let (head, body) = received.split_once("\r\n\r\n").unwrap();
+        assert!(head.contains("Transfer-Encoding: chunked"));
+        assert!(!head.contains("Connection"));
+        assert_eq!(body, "7\r\nhello, \r\n5\r\nworld\r\n0\r\n\r\n");
+    }
+
+    #[test]
+    fn streams_without_a_length_end_with_the_connection_for_http_1_0() {
+        let mut response = streamed();
+        response.close_delimited = true;
+        let received = sent(response);
+        let (head, body) = received.split_once("\r\n\r\n").unwrap();
+        assert!(!head.contains("Transfer-Encoding"));
+        assert!(head.contains("Connection: close"));
+        assert_eq!(body, "hello, world");
+    }
+
+    #[test]
+    fn streams_with_a_length_are_sent_as_is() {
+        let mut headers = HashMap::new();
+        headers.insert(Header::ContentLength, "5".to_string());
+        let response = Response::builder_stream(Status::new(StatusCode::Ok), headers, |out| {
+            out.write_all(b"hello")
+        });
+        let received = sent(response);
+        let (head, body) = received.split_once("\r\n\r\n").unwrap();
+        assert!(head.contains("Content-Length: 5"));
+        assert!(!head.contains("Transfer-Encoding"));
+        assert_eq!(body, "hello");
+    }
+}
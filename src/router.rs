@@ -1,65 +1,318 @@
-use crate::http::{RequestMethod, Status, StatusCode};
+use crate::extract::Handler;
+use crate::http::{Header, RequestMethod, Status, StatusCode};
+use crate::middleware::{Middleware, Next};
+use crate::pattern::{Params, Pattern, Segment};
 use crate::request::Request;
 use crate::response::Response;
-use crate::server::RequestHandler;
-use std::collections::HashMap;
+use crate::server::{BoxFuture, RequestHandler};
+use crate::url;
+use std::collections::{BTreeSet, HashMap};
 use std::sync::Arc;
+use thiserror::Error;
 
-pub struct Router {
-    routes: HashMap<
-        (RequestMethod, String),
-        Arc<dyn Fn(Request) -> Result<Response, Response> + Send + Sync>,
-    >,
+pub struct Route<S> {
+    handler: RequestHandler<S>,
+    pattern: Pattern,
+    // Run around this route's handler only, inside the router's middleware
+    middleware: Vec<Middleware>,
+    // Lets URLs for the route be built with `url_for`
+    name: Option<String>,
 }
 
-impl Router {
+impl<S> Route<S> {
+    // Adds middleware to this route. Middleware runs in the order it was
+    // added, the first one outermost.
+    pub fn layer<F>(&mut self, middleware: F) -> &mut Route<S>
+    where
+        F: for<'a> Fn(Request, Next<'a>) -> BoxFuture<'a, Result<Response, Response>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    // Names the route so its URLs can be built from the name. Routes for
+    // other methods on the same path may share the name.
+    pub fn name(&mut self, name: &str) -> &mut Route<S> {
+        self.name = Some(name.to_string());
+        self
+    }
+}
+
+// Routes requests to handlers that are given a shared state of type `S`
+pub struct Router<S> {
+    routes: HashMap<(RequestMethod, String), Route<S>>,
+    // Run around every request, matched or not
+    middleware: Vec<Middleware>,
+    // Answer requests no route matches, by path or by method
+    fallback: Option<RequestHandler<S>>,
+    method_not_allowed: Option<RequestHandler<S>>,
+}
+
+impl<S> Default for Router<S> {
+    fn default() -> Self {
+        Router::new()
+    }
+}
+
+impl<S> Router<S> {
     pub fn new() -> Self {
         Router {
             routes: HashMap::new(),
+            middleware: Vec::new(),
+            fallback: None,
+            method_not_allowed: None,
         }
     }
 
-    pub fn add_route<F>(&mut self, method: RequestMethod, path: &str, handler: F)
+    // Adds a handler for `path`. A path with `{name}` segments matches
+    // targets segment by segment, and a trailing `{*name}` matches the rest of
+    // the target; the matched values are what `Path` extracts. Any other path
+    // matches every target it prefixes.
+    //
+    // `{name:u32}` or `{name:[0-9a-f]{64}}` only matches values the type
+    // parses or the pattern matches, so other values fall through to other
+    // routes, and the most specific route that matches wins. Panics on an
+    // invalid path, or one some target would match as specifically as another
+    // route for the method, e.g. `{n:u32}` next to `{id:[0-9]+}`.
+    pub fn add_route<H, Args>(
+        &mut self,
+        method: RequestMethod,
+        path: &str,
+        handler: H,
+    ) -> &mut Route<S>
     where
-        F: Fn(Request) -> Result<Response, Response> + Send + Sync + 'static,
+        H: Handler<S, Args>,
     {
-        self.routes
-            .insert((method, path.to_string()), Arc::new(handler));
-    }
-
-    pub fn route(&self, req: Request) -> Result<Response, Response> {
-        let (method, key) = (req.method.clone(), req.target.clone());
-        if let Some(handler) = self.contains_prefix(&method, &key) {
-            handler(req)
-        } else {
-            Err(Response::builder(
-                Status::new(StatusCode::NotFound),
-                "404 Not Found".to_string(),
-                HashMap::new(),
-            ))
+        let route = Route {
+            handler: Arc::new(move |state: Arc<S>, req: Request| handler.call(state, req)),
+            pattern: parse_pattern(path),
+            middleware: Vec::new(),
+            name: None,
+        };
+        self.insert(method, route)
+    }
+
+    // Adding a route again for the same method and path replaces it
+    fn insert(&mut self, method: RequestMethod, route: Route<S>) -> &mut Route<S> {
+        let path = route.pattern.path().to_string();
+        for ((other_method, other_path), other) in &self.routes {
+            if *other_method == method
+                && *other_path != path
+                && other.pattern.conflicts_with(&route.pattern)
+            {
+                panic!(
+                    "route {} {} conflicts with {} {}",
+                    method, path, other_method, other_path
+                );
+            }
         }
+        let key = (method, path);
+        self.routes.insert(key.clone(), route);
+        self.routes.get_mut(&key).unwrap()
     }
 
-    pub fn get_prefixes(&self) -> Vec<String> {
-        self.routes.keys().map(|(_, path)| path.clone()).collect()
+    // Mounts the routes of another router under `prefix`, so its `/x` is
+    // served at `prefix/x` and its `/` at `prefix`. Its router-wide middleware
+    // keeps applying to its own routes only.
+    pub fn nest(&mut self, prefix: &str, router: Router<S>) -> &mut Router<S> {
+        let prefix = prefix.trim_end_matches('/');
+        for ((method, path), mut route) in router.routes {
+            let path = match path.as_str() {
+                "/" if !prefix.is_empty() => prefix.to_string(),
+                _ => format!("{}{}", prefix, path),
+            };
+            let mut middleware = router.middleware.clone();
+            middleware.append(&mut route.middleware);
+            route.middleware = middleware;
+            route.pattern = parse_pattern(&path);
+            self.insert(method, route);
+        }
+        self
     }
 
-    pub fn contains_prefix(&self, method: &RequestMethod, prefix: &str) -> Option<&RequestHandler> {
-        self.routes.iter().find_map(|((_method, key), handler)| {
-            if key == "/" && prefix == "/" && method == _method {
-                Some(handler)
-            } else if prefix.starts_with(key) && key != "/" && method == _method {
-                Some(handler)
-            } else {
-                None
+    // Adds the routes of a router built elsewhere, e.g. in a feature's module
+    pub fn merge(&mut self, router: Router<S>) -> &mut Router<S> {
+        self.nest("", router)
+    }
+
+    // Registers routes that share a prefix and middleware on the router
+    // `build` is given, with paths relative to `prefix`
+    pub fn group<F>(&mut self, prefix: &str, build: F) -> &mut Router<S>
+    where
+        F: FnOnce(&mut Router<S>),
+    {
+        let mut group = Router::new();
+        build(&mut group);
+        self.nest(prefix, group)
+    }
+
+    // Answers requests whose path no route matches, in place of a plain 404.
+    // Only the router that serves requests uses its fallbacks; those of
+    // nested and merged routers are dropped.
+    pub fn fallback<H, Args>(&mut self, handler: H) -> &mut Router<S>
+    where
+        H: Handler<S, Args>,
+    {
+        self.fallback = Some(Arc::new(move |state, req| handler.call(state, req)));
+        self
+    }
+
+    // Answers requests whose path has routes, but none for their method, in
+    // place of a plain 405. `Allow` still lists the methods the path takes.
+    pub fn method_not_allowed<H, Args>(&mut self, handler: H) -> &mut Router<S>
+    where
+        H: Handler<S, Args>,
+    {
+        self.method_not_allowed = Some(Arc::new(move |state, req| handler.call(state, req)));
+        self
+    }
+
+    // Adds middleware around the whole router. It sees requests before they
+    // are matched to a route, so it may also rewrite them.
+    pub fn layer<F>(&mut self, middleware: F) -> &mut Router<S>
+    where
+        F: for<'a> Fn(Request, Next<'a>) -> BoxFuture<'a, Result<Response, Response>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    pub async fn route(&self, state: &Arc<S>, req: Request) -> Result<Response, Response>
+    where
+        S: Send + Sync,
+    {
+        let endpoint = |mut req: Request| -> BoxFuture<'_, Result<Response, Response>> {
+            Box::pin(async move {
+                match self.find_route(&req.method, &req.target) {
+                    Some((route, params)) => {
+                        req.params = params;
+                        let handler = |req: Request| (route.handler)(state.clone(), req);
+                        Next::new(&route.middleware, &handler).run(req).await
+                    }
+                    None => self.unmatched(state, req).await,
+                }
+            })
+        };
+        Next::new(&self.middleware, &endpoint).run(req).await
+    }
+
+    async fn unmatched(&self, state: &Arc<S>, req: Request) -> Result<Response, Response> {
+        let allowed = self.allowed_methods(&req.target);
+        if allowed.is_empty() {
+            return match &self.fallback {
+                Some(fallback) => fallback(state.clone(), req).await,
+                None => Err(Response::builder(
+                    Status::new(StatusCode::NotFound),
+                    "404 Not Found".to_string(),
+                    HashMap::new(),
+                )),
+            };
+        }
+        let allow = allowed.join(", ");
+        match &self.method_not_allowed {
+            // RFC 9110 requires Allow on a 405, whoever writes the rest of it
+            Some(method_not_allowed) => {
+                let mut result = method_not_allowed(state.clone(), req).await;
+                let (Ok(response) | Err(response)) = &mut result;
+                response.headers.entry(Header::Allow).or_insert(allow);
+                result
+            }
+            None => {
+                let mut headers = HashMap::new();
+                headers.insert(Header::Allow, allow);
+                Err(Response::builder(
+                    Status::new(StatusCode::MethodNotAllowed),
+                    "405 Method Not Allowed".to_string(),
+                    headers,
+                ))
+            }
+        }
+    }
+
+    // The methods some route takes `target` with, HEAD included where GET is
+    pub fn allowed_methods(&self, target: &str) -> Vec<String> {
+        let mut methods = BTreeSet::new();
+        for ((method, _), route) in &self.routes {
+            if route.pattern.matches(target).is_some() {
+                methods.insert(method.to_string());
+                if *method == RequestMethod::GET {
+                    methods.insert(RequestMethod::HEAD.to_string());
+                }
+            }
+        }
+        methods.into_iter().collect()
+    }
+
+    // The URL of a named route, e.g. `/users/bob` for `users` at `/users/{name}`
+    // with `[("name", "bob")]`
+    pub fn url_for(&self, name: &str, params: &[(&str, &str)]) -> Result<String, UrlError> {
+        self.urls().url_for(name, params)
+    }
+
+    // The paths of all named routes, for handlers to build URLs with
+    pub fn urls(&self) -> Urls {
+        let mut paths: HashMap<String, Pattern> = HashMap::new();
+        for ((_, path), route) in &self.routes {
+            if let Some(name) = &route.name {
+                if let Some(other) = paths.insert(name.clone(), route.pattern.clone()) {
+                    assert!(
+                        other.path() == path,
+                        "route name {} is used for both {} and {}",
+                        name,
+                        other.path(),
+                        path
+                    );
+                }
             }
+        }
+        Urls { paths }
+    }
+
+    pub fn has_route(&self, method: &RequestMethod, target: &str) -> bool {
+        self.find_route(method, target).is_some()
+    }
+
+    // HEAD is answered like GET unless a route handles it explicitly
+    fn find_route(&self, method: &RequestMethod, target: &str) -> Option<(&Route<S>, Params)> {
+        self.match_prefix(method, target).or_else(|| match method {
+            RequestMethod::HEAD => self.match_prefix(&RequestMethod::GET, target),
+            _ => None,
         })
     }
 
+    pub fn get_prefixes(&self) -> Vec<String> {
+        self.routes.keys().map(|(_, path)| path.clone()).collect()
+    }
+
+    // The most specific route whose path prefixes the target, so `/files/x`
+    // can have its own handler next to `/files`
+    pub fn contains_prefix(
+        &self,
+        method: &RequestMethod,
+        prefix: &str,
+    ) -> Option<&RequestHandler<S>> {
+        self.match_prefix(method, prefix)
+            .map(|(route, _)| &route.handler)
+    }
+
+    fn match_prefix(&self, method: &RequestMethod, prefix: &str) -> Option<(&Route<S>, Params)> {
+        self.routes
+            .iter()
+            .filter(|((_method, _), _)| method == _method)
+            .filter_map(|(_, route)| Some((route, route.pattern.matches(prefix)?)))
+            .max_by_key(|(route, _)| route.pattern.specificity())
+    }
+
     fn find_prefix<'a>(target: &'a str, prefixes: &'a [String]) -> Option<&'a str> {
         prefixes
             .iter()
-            .find(|&&ref prefix| target.starts_with(prefix))
+            .find(|prefix| target.starts_with(prefix.as_str()))
             .map(|prefix| prefix.as_str())
     }
 
@@ -71,3 +324,295 @@ impl Router {
         }
     }
 }
+
+#[derive(Debug, Error, PartialEq)]
+pub enum UrlError {
+    #[error("no route is named {0}")]
+    UnknownRoute(String),
+    #[error("route {route} needs a value for {param}")]
+    MissingParameter { route: String, param: String },
+    #[error("route {route} doesn't match {value} for {param}")]
+    InvalidParameter {
+        route: String,
+        param: String,
+        value: String,
+    },
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Urls {
+    paths: HashMap<String, Pattern>,
+}
+
+impl Urls {
+    // Fills in the `{name}` segments of a named route's path, percent-encoding
+    // the values. A `{*name}` value may span segments, which keep their `/`.
+    pub fn url_for(&self, name: &str, params: &[(&str, &str)]) -> Result<String, UrlError> {
+        let pattern = self
+            .paths
+            .get(name)
+            .ok_or_else(|| UrlError::UnknownRoute(name.to_string()))?;
+        let segments = match pattern.segments() {
+            Some(segments) => segments,
+            None => return Ok(pattern.path().to_string()),
+        };
+
+        let mut url = String::new();
+        for segment in segments {
+            url.push('/');
+            let (key, wildcard) = match segment {
+                Segment::Literal(literal) => {
+                    url.push_str(literal);
+                    continue;
+                }
+                Segment::Param { name, .. } => (name, false),
+                Segment::Wildcard(name) => (name, true),
+            };
+            let value = params
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, value)| *value)
+                // A single segment can't be empty, or the route wouldn't match
+                .filter(|value| wildcard || !value.is_empty())
+                .ok_or_else(|| UrlError::MissingParameter {
+                    route: name.to_string(),
+                    param: key.to_string(),
+                })?;
+            if let Segment::Param {
+                constraint: Some(constraint),
+                ..
+            } = segment
+            {
+                if !constraint.accepts(value) {
+                    return Err(UrlError::InvalidParameter {
+                        route: name.to_string(),
+                        param: key.to_string(),
+                        value: value.to_string(),
+                    });
+                }
+            }
+            if wildcard {
+                let parts = value
+                    .split('/')
+                    .map(url::percent_encode)
+                    .collect::<Vec<_>>();
+                url.push_str(&parts.join("/"));
+            } else {
+                url.push_str(&url::percent_encode(value));
+            }
+        }
+        Ok(url)
+    }
+}
+
+fn parse_pattern(path: &str) -> Pattern {
+    Pattern::parse(path).unwrap_or_else(|e| panic!("invalid route {}: {}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body;
+    use crate::extract::{Accept, Body, Header as TypedHeader, Host, Path, Query};
+    use crate::form::FormData;
+    use bytes::Bytes;
+
+    fn request(method: RequestMethod, target: &str) -> Request {
+        Request {
+            method,
+            target: target.to_string(),
+            version: "HTTP/1.1".to_string(),
+            body: body::Body::empty(),
+            headers: HashMap::new(),
+            params: Vec::new(),
+        }
+    }
+
+    async fn send<S: Send + Sync>(router: &Router<S>, state: S, req: Request) -> Response {
+        match router.route(&Arc::new(state), req).await {
+            Ok(response) | Err(response) => response,
+        }
+    }
+
+    fn body(response: &Response) -> &str {
+        std::str::from_utf8(&response.body).unwrap()
+    }
+
+    fn marker() -> Header {
+        Header::Custom("X-Layer".to_string())
+    }
+
+    // Appends its name to X-Layer on the way out
+    fn layer(
+        name: &'static str,
+    ) -> impl for<'a> Fn(Request, Next<'a>) -> BoxFuture<'a, Result<Response, Response>> {
+        move |req, next| {
+            Box::pin(async move {
+                let mut response = next.run(req).await?;
+                let layers = response.headers.entry(marker()).or_default();
+                layers.push_str(name);
+                Ok(response)
+            })
+        }
+    }
+
+    fn a(_: &()) -> &'static str {
+        "a"
+    }
+
+    fn b(_: &()) -> &'static str {
+        "b"
+    }
+
+    #[tokio::test]
+    async fn route_layers_run_for_their_route_only() {
+        let mut router = Router::new();
+        router
+            .add_route(RequestMethod::GET, "/a", a)
+            .layer(layer("1"))
+            .layer(layer("2"));
+        router.add_route(RequestMethod::GET, "/b", b);
+        router.layer(layer("r"));
+
+        let response = send(&router, (), request(RequestMethod::GET, "/a")).await;
+        assert_eq!(body(&response), "a");
+        // The first layer added is outermost, and the router's is outside both
+        assert_eq!(response.headers.get(&marker()).unwrap(), "21r");
+
+        let response = send(&router, (), request(RequestMethod::GET, "/b")).await;
+        assert_eq!(body(&response), "b");
+        assert_eq!(response.headers.get(&marker()).unwrap(), "r");
+
+        let mut nested = Router::new();
+        nested.nest("/x", router);
+        let response = send(&nested, (), request(RequestMethod::GET, "/x/a")).await;
+        assert_eq!(response.headers.get(&marker()).unwrap(), "21r");
+        let response = send(&nested, (), request(RequestMethod::GET, "/x/b")).await;
+        assert_eq!(response.headers.get(&marker()).unwrap(), "r");
+    }
+
+    fn query(
+        _: &(),
+        Query(first): Query<HashMap<String, String>>,
+        Query(all): Query<FormData>,
+    ) -> String {
+        format!("{:?} {:?}", first.get("a"), all.get_all("a"))
+    }
+
+    fn upload(
+        _: &(),
+        TypedHeader(Host(host)): TypedHeader<Host>,
+        TypedHeader(Accept(accept)): TypedHeader<Accept>,
+        Body(bytes): Body<Bytes>,
+    ) -> String {
+        format!("{} {} {:?}", host, accept, bytes.to_vec())
+    }
+
+    fn text(_: &(), Body(text): Body<String>) -> String {
+        text
+    }
+
+    #[tokio::test]
+    async fn handlers_get_the_query_headers_and_body() {
+        let mut router = Router::new();
+        router.add_route(RequestMethod::GET, "/query", query);
+        router.add_route(RequestMethod::POST, "/upload", upload);
+        router.add_route(RequestMethod::POST, "/text", text);
+
+        let req = request(RequestMethod::GET, "/query?a=1&b=2&a=x+y");
+        let response = send(&router, (), req).await;
+        assert_eq!(body(&response), r#"Some("1") ["1", "x y"]"#);
+        let response = send(&router, (), request(RequestMethod::GET, "/query")).await;
+        assert_eq!(body(&response), "None []");
+
+        let mut req = request(RequestMethod::POST, "/upload");
+        req.headers.insert(Header::Host, "example.com".to_string());
+        req.headers.insert(Header::Accept, "text/plain".to_string());
+        req.body = body::Body::from_bytes(b"a\x00b".to_vec());
+        let response = send(&router, (), req).await;
+        assert_eq!(body(&response), "example.com text/plain [97, 0, 98]");
+
+        // A typed header that is missing refuses the request
+        let mut req = request(RequestMethod::POST, "/upload");
+        req.headers.insert(Header::Host, "example.com".to_string());
+        let response = send(&router, (), req).await;
+        assert_eq!(response.status.code, StatusCode::BadRequest);
+
+        let mut req = request(RequestMethod::POST, "/text");
+        req.body = body::Body::from_bytes("é".as_bytes().to_vec());
+        assert_eq!(body(&send(&router, (), req).await), "é");
+        let mut req = request(RequestMethod::POST, "/text");
+        req.body = body::Body::from_bytes(vec![0xff]);
+        let response = send(&router, (), req).await;
+        assert_eq!(response.status.code, StatusCode::BadRequest);
+    }
+
+    async fn greet(greeting: Arc<String>, Path((name, times)): Path<(String, u32)>) -> String {
+        tokio::task::yield_now().await;
+        format!("{}, {}", greeting, name).repeat(times as usize)
+    }
+
+    #[tokio::test]
+    async fn async_handlers_get_the_shared_state_and_path() {
+        let mut router = Router::new();
+        router.add_route(RequestMethod::GET, "/greet/{name}/{times}", greet);
+        let state = "hi".to_string();
+
+        let req = request(RequestMethod::GET, "/greet/b%20b/2");
+        let response = send(&router, state.clone(), req).await;
+        assert_eq!(response.status.code, StatusCode::Ok);
+        assert_eq!(body(&response), "hi, b bhi, b b");
+
+        let req = request(RequestMethod::GET, "/greet/bob/twice");
+        let response = send(&router, state, req).await;
+        assert_eq!(response.status.code, StatusCode::BadRequest);
+    }
+
+    fn custom(status: StatusCode, body: &str) -> Response {
+        Response::builder(Status::new(status), body.to_string(), HashMap::new())
+    }
+
+    fn not_found(_: &(), req: Request) -> Response {
+        custom(StatusCode::NotFound, &format!("no {}", req.target))
+    }
+
+    fn method_not_allowed(_: &(), req: Request) -> Result<Response, Response> {
+        Err(custom(
+            StatusCode::MethodNotAllowed,
+            &format!("no {}", req.method),
+        ))
+    }
+
+    #[tokio::test]
+    async fn unmatched_requests_go_to_the_fallbacks() {
+        let mut router = Router::new();
+        router.add_route(RequestMethod::GET, "/a", a);
+        router.add_route(RequestMethod::PUT, "/a", a);
+
+        let response = send(&router, (), request(RequestMethod::GET, "/b")).await;
+        assert_eq!(response.status.code, StatusCode::NotFound);
+        assert_eq!(body(&response), "404 Not Found");
+        let response = send(&router, (), request(RequestMethod::DELETE, "/a")).await;
+        assert_eq!(response.status.code, StatusCode::MethodNotAllowed);
+        assert_eq!(
+            response.headers.get(&Header::Allow).unwrap(),
+            "GET, HEAD, PUT"
+        );
+
+        router
+            .fallback(not_found)
+            .method_not_allowed(method_not_allowed);
+        let response = send(&router, (), request(RequestMethod::GET, "/b")).await;
+        assert_eq!(response.status.code, StatusCode::NotFound);
+        assert_eq!(body(&response), "no /b");
+        let response = send(&router, (), request(RequestMethod::DELETE, "/a")).await;
+        assert_eq!(response.status.code, StatusCode::MethodNotAllowed);
+        assert_eq!(body(&response), "no DELETE");
+        assert_eq!(
+            response.headers.get(&Header::Allow).unwrap(),
+            "GET, HEAD, PUT"
+        );
+        let response = send(&router, (), request(RequestMethod::GET, "/a")).await;
+        assert_eq!(body(&response), "a");
+    }
+}
@@ -1,149 +1,1797 @@
-use crate::http::{RequestMethod, Status, StatusCode};
-use crate::server::RequestHandler;
-use std::collections::HashMap;
-use std::sync::Arc;
-use crate::http::Header;
-use std::path::Path;
-use std::env;
-use crate::http::ContentType;
+use crate::archive::{self, ArchiveFormat};
+use crate::conditional::{self, Precondition, Validators};
+use crate::config::Config;
+use crate::date;
+use crate::digest::{self, DigestAlgorithm, HashingReader};
+use crate::extract::{self, UserAgent};
+use crate::files::{self, Representation, WriteMode};
+use crate::http::{ContentType, Header, RequestMethod, Status, StatusCode};
+use crate::json::JsonValue;
+use crate::listing::{self, SortKey};
+use crate::middleware;
+use crate::range::{self, RangeError};
 use crate::request::Request;
 use crate::response::Response;
+use crate::router::Router;
+use crate::sandbox::{Sandbox, SandboxError};
+use crate::state::AppState;
+use crate::tus::{self, UploadLock, UploadStore};
+use crate::url;
+use crate::webdav;
+use std::collections::HashMap;
+use std::fs::{File, Metadata};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+// Every route the server answers
+pub fn router(config: &Config) -> Router<AppState> {
+    let mut router = Router::new();
+    if config.log_requests {
+        router.layer(middleware::log_requests);
+    }
+    router
+        .add_route(RequestMethod::GET, "/", root_handler)
+        .name("root");
+    router
+        .add_route(RequestMethod::GET, "/echo/{*text}", echo_handler)
+        .name("echo");
+    router.add_route(RequestMethod::POST, "/echo", echo_body_handler);
+    router
+        .add_route(RequestMethod::GET, "/user-agent", user_agent_handler)
+        .name("user_agent");
+    router.nest("/files", files_router(config));
+    router.group("/uploads", |uploads| {
+        uploads.add_route(RequestMethod::OPTIONS, "/{*id}", tus_options_handler);
+        uploads
+            .add_route(RequestMethod::HEAD, "/{*id}", tus_head_handler)
+            .name("upload");
+        uploads.add_route(RequestMethod::PATCH, "/{*id}", tus_patch_handler);
+        uploads.add_route(RequestMethod::DELETE, "/{*id}", tus_delete_handler);
+    });
+    router
+}
+
+fn files_router(config: &Config) -> Router<AppState> {
+    let mut router = Router::new();
+    router
+        .add_route(RequestMethod::GET, "/{*path}", files_handler)
+        .name("files");
+    router.add_route(RequestMethod::HEAD, "/{*path}", files_handler_head);
+    router.add_route(RequestMethod::PUT, "/{*path}", files_handler_replace);
+    router.add_route(RequestMethod::DELETE, "/{*path}", files_handler_delete);
+    router.add_route(RequestMethod::OPTIONS, "/{*path}", files_options_handler);
+    if config.content_addressed {
+        router.add_route(RequestMethod::POST, "/{*path}", files_handler_cas_create);
+        router
+            .add_route(RequestMethod::GET, "/sha256/{*digest}", files_handler_cas)
+            .name("blob");
+        router.add_route(RequestMethod::HEAD, "/sha256/{*digest}", files_handler_cas);
+    } else {
+        router.add_route(RequestMethod::POST, "/{*path}", files_handler_create);
+    }
+    router.merge(webdav_router());
+    router
+}
+
+fn webdav_router() -> Router<AppState> {
+    let mut router = Router::new();
+    router.add_route(RequestMethod::PROPFIND, "/{*path}", files_handler_propfind);
+    router.add_route(RequestMethod::MKCOL, "/{*path}", files_handler_mkcol);
+    router.add_route(RequestMethod::COPY, "/{*path}", files_handler_copy);
+    router.add_route(RequestMethod::MOVE, "/{*path}", files_handler_move);
+    router
+}
 
+pub fn root_handler(_state: &AppState) -> StatusCode {
+    StatusCode::Ok
+}
 
-pub fn root_handler(req: Request) -> Result<Response, Response> {
-    let body = "";
+pub fn echo_handler(
+    _state: &AppState,
+    extract::Path(text): extract::Path<String>,
+    headers: HashMap<Header, String>,
+) -> Result<Response, Response> {
+    Ok(text_response(text, headers))
+}
+
+// Echoes a JSON body back as parsed, or a urlencoded form as a JSON object
+// mapping each field to the values sent for it
+pub fn echo_body_handler(
+    _state: &AppState,
+    mut req: Request,
+) -> Result<extract::Json<JsonValue>, Response> {
+    let is_form = req
+        .headers
+        .get(&Header::ContentType)
+        .is_some_and(|content_type| {
+            let media_type = content_type.split(';').next().unwrap_or_default();
+            media_type
+                .trim()
+                .eq_ignore_ascii_case("application/x-www-form-urlencoded")
+        });
+    if !is_form {
+        return req.json::<JsonValue>().map(extract::Json);
+    }
+    let mut fields: Vec<(String, JsonValue)> = Vec::new();
+    for (key, value) in req.form()?.iter() {
+        match fields.iter_mut().find(|(k, _)| k == key) {
+            Some((_, JsonValue::Array(values))) => values.push(value.into()),
+            _ => fields.push((key.to_string(), JsonValue::Array(vec![value.into()]))),
+        }
+    }
+    Ok(extract::Json(JsonValue::Object(fields)))
+}
+
+pub fn user_agent_handler(
+    _state: &AppState,
+    user_agent: Option<extract::Header<UserAgent>>,
+    headers: HashMap<Header, String>,
+) -> Result<Response, Response> {
+    let user_agent = match user_agent {
+        Some(extract::Header(UserAgent(user_agent))) => user_agent,
+        None => "No User-Agent found".to_string(),
+    };
+    Ok(text_response(user_agent, headers))
+}
+
+// A 200 text/plain response. The request headers are passed on so the body
+// is compressed the way the client accepts.
+fn text_response(body: String, request_headers: HashMap<Header, String>) -> Response {
     let mut headers = HashMap::new();
     headers.insert(Header::ContentType, ContentType::TextPlain.to_string());
     headers.insert(Header::ContentLength, body.len().to_string());
-    headers.extend(req.headers);
-    Ok(Response::builder(
+    headers.extend(request_headers);
+    Response::builder(
         Status {
             code: StatusCode::Ok,
             message: "OK".to_string(),
         },
-        body.to_string(),
+        body,
         headers,
-    ))
+    )
+}
+
+pub fn files_handler(state: &AppState, req: Request) -> Result<Response, Response> {
+    let mut filepath = resolve_file_path(state, &req)?;
+    if filepath.is_dir() {
+        if let Some(format) = req.query_param("archive") {
+            return Ok(files_archive_response(&filepath, &format));
+        }
+        match directory_index(state, &filepath)? {
+            Some(index) => filepath = index,
+            None if state.config.listings => {
+                return Ok(files_listing_response(state, &req, &filepath))
+            }
+            None => {}
+        }
+    }
+    let filepath = filepath.as_path();
+    let metadata = match std::fs::metadata(filepath) {
+        Ok(metadata) if metadata.is_file() => metadata,
+        _ => {
+            return Ok(Response::builder(
+                Status {
+                    code: StatusCode::NotFound,
+                    message: "Not Found".to_string(),
+                },
+                "404 Not Found".to_string(),
+                HashMap::new(),
+            ))
+        }
+    };
+
+    Ok(serve_file(files_sandbox(state)?, &req, filepath, &metadata))
 }
 
-pub fn echo_handler(req: Request) -> Result<Response, Response> {
-    let body = req
-        .target
-        .strip_prefix("/echo")
-        .and_then(|stripped| stripped.strip_prefix('/'))
-        .map(|stripped| stripped.to_string())
+// Serves a regular file with content negotiation, validators, conditional
+// requests, ranges and digests
+fn serve_file(sandbox: &Sandbox, req: &Request, filepath: &Path, metadata: &Metadata) -> Response {
+    let accept_encoding = req
+        .headers
+        .get(&Header::AcceptEncoding)
+        .cloned()
         .unwrap_or_default();
+    let representation = files::negotiate(sandbox, filepath, &accept_encoding);
+    let content_encoding = representation.coding();
+    // A precompressed sibling changes on its own, so its validators come from
+    // its own metadata
+    let validators = match &representation {
+        Representation::Precompressed(precompressed) => {
+            match std::fs::metadata(&precompressed.path) {
+                Ok(metadata) => files::validators(&metadata, content_encoding.as_deref()),
+                Err(_) => {
+                    return Response::builder(
+                        Status::new(StatusCode::NotFound),
+                        "404 Not Found".to_string(),
+                        HashMap::new(),
+                    )
+                }
+            }
+        }
+        _ => files::validators(metadata, content_encoding.as_deref()),
+    };
+
+    let mut headers = HashMap::new();
+    insert_validators(&mut headers, &validators);
+    headers.insert(Header::Vary, "Accept-Encoding".to_string());
+    match conditional::evaluate(req, &validators) {
+        Precondition::Proceed => {}
+        Precondition::NotModified => {
+            return Response::builder(
+                Status::new(StatusCode::NotModified),
+                "".to_string(),
+                headers,
+            )
+        }
+        Precondition::Failed => {
+            return Response::builder(
+                Status::new(StatusCode::PreconditionFailed),
+                "".to_string(),
+                HashMap::new(),
+            )
+        }
+    }
+
+    if let Some(range) = req.headers.get(&Header::Range) {
+        if let Some(response) = files_range_response(req, filepath, metadata, range) {
+            return response;
+        }
+    }
+
+    let (mut body, len) = match files::open_representation(filepath, &representation) {
+        Ok(opened) => opened,
+        Err(_) => {
+            return Response::builder(
+                Status {
+                    code: StatusCode::NotFound,
+                    message: "Not Found".to_string(),
+                },
+                "404 Not Found".to_string(),
+                HashMap::new(),
+            )
+        }
+    };
+    if let Some(len) = len {
+        headers.insert(Header::ContentLength, len.to_string());
+    }
+    headers.insert(
+        Header::ContentType,
+        ContentType::from_path(filepath).to_string(),
+    );
+    headers.insert(Header::AcceptRanges, "bytes".to_string());
+    if let Some(content_encoding) = content_encoding {
+        headers.insert(Header::ContentEncoding, content_encoding);
+    }
+    // A full response carries the whole representation, so both digests
+    // cover the same bytes and each algorithm reads them once
+    let mut computed: Option<(DigestAlgorithm, String)> = None;
+    for (want, header) in [
+        (Header::WantContentDigest, Header::ContentDigest),
+        (Header::WantReprDigest, Header::ReprDigest),
+    ] {
+        let algorithm = match digest_algorithm(req, &want) {
+            Some(algorithm) => algorithm,
+            None => continue,
+        };
+        let field = match &computed {
+            Some((computed, field)) if *computed == algorithm => field.clone(),
+            _ => {
+                let digest = files::open_representation(filepath, &representation)
+                    .and_then(|(reader, _)| algorithm.digest_reader(reader));
+                match digest {
+                    Ok(digest) => {
+                        let field = digest::format_digest_field(&[(algorithm, digest)]);
+                        computed = Some((algorithm, field.clone()));
+                        field
+                    }
+                    Err(_) => continue,
+                }
+            }
+        };
+        headers.insert(header, field);
+    }
+    Response::builder_stream(Status::new(StatusCode::Ok), headers, move |out| match len {
+        Some(len) => files::copy_exact(body, len, out),
+        None => io::copy(&mut body, out).map(|_| ()),
+    })
+}
+
+// Streams an archive of a directory as it is read, so nothing is staged on
+// disk or in memory
+fn files_archive_response(dir: &Path, format: &str) -> Response {
+    let format = match ArchiveFormat::from_string(format) {
+        Some(format) => format,
+        None => {
+            return Response::builder(
+                Status::new(StatusCode::BadRequest),
+                format!("Unsupported archive format: {}", format),
+                HashMap::new(),
+            )
+        }
+    };
+    let name = dir
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "files".to_string());
+    let filename = format!("{}.{}", name, format.extension());
+    // A plain ASCII filename for old clients, and the exact one per RFC 6266
+    let fallback: String = filename
+        .chars()
+        .map(|c| {
+            if c.is_ascii_graphic() && c != '"' && c != '\\' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    let mut headers = HashMap::new();
+    headers.insert(Header::ContentType, format.content_type().to_string());
+    headers.insert(
+        Header::ContentDisposition,
+        format!(
+            "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+            fallback,
+            url::percent_encode(&filename)
+        ),
+    );
+    let dir = dir.to_path_buf();
+    Response::builder_stream(Status::new(StatusCode::Ok), headers, move |out| {
+        archive::write_archive(format, &dir, &name, out)
+    })
+}
+
+// Answers a Range request with 206 or 416, always from the identity
+// representation. Returns None when the full file should be sent instead: the
+// header is malformed, If-Range doesn't match, or the file can't be read.
+fn files_range_response(
+    req: &Request,
+    filepath: &Path,
+    metadata: &Metadata,
+    range: &str,
+) -> Option<Response> {
+    let validators = files::validators(metadata, None);
+    if let Some(if_range) = req.headers.get(&Header::IfRange) {
+        if !conditional::if_range_matches(if_range, &validators) {
+            return None;
+        }
+    }
+
+    let len = metadata.len();
+    let content_type = ContentType::from_path(filepath).to_string();
+    let mut headers = HashMap::new();
+    headers.insert(Header::AcceptRanges, "bytes".to_string());
+    insert_validators(&mut headers, &validators);
+
+    let ranges = match range::parse_range(range, len) {
+        Ok(ranges) => ranges,
+        Err(RangeError::Invalid) => return None,
+        Err(RangeError::Unsatisfiable) => {
+            headers.insert(Header::ContentRange, format!("bytes */{}", len));
+            return Some(Response::builder(
+                Status::new(StatusCode::RangeNotSatisfiable),
+                "".to_string(),
+                headers,
+            ));
+        }
+    };
+
+    let mut file = File::open(filepath).ok()?;
+    let boundary = match ranges.as_slice() {
+        [range] => {
+            headers.insert(Header::ContentRange, range.content_range(len));
+            headers.insert(Header::ContentLength, range.len().to_string());
+            headers.insert(Header::ContentType, content_type.clone());
+            None
+        }
+        _ => {
+            let boundary = range::multipart_boundary();
+            let body_len = range::multipart_byteranges_len(&ranges, &boundary, &content_type, len);
+            headers.insert(Header::ContentLength, body_len.to_string());
+            headers.insert(
+                Header::ContentType,
+                format!("multipart/byteranges; boundary={}", boundary),
+            );
+            Some(boundary)
+        }
+    };
+    let write_body = move |file: &mut File, out: &mut dyn Write| match &boundary {
+        None => files::copy_range(file, &ranges[0], out),
+        Some(boundary) => range::write_multipart_byteranges(
+            out,
+            &ranges,
+            boundary,
+            &content_type,
+            len,
+            |range, out| files::copy_range(file, range, out),
+        ),
+    };
+    if let Some(algorithm) = digest_algorithm(req, &Header::WantContentDigest) {
+        let mut hasher = algorithm.hasher();
+        write_body(&mut file, &mut hasher).ok()?;
+        let digest = digest::format_digest_field(&[(algorithm, hasher.finish())]);
+        headers.insert(Header::ContentDigest, digest);
+    }
+    insert_repr_digest(req, &mut headers, filepath);
+    Some(Response::builder_stream(
+        Status::new(StatusCode::PartialContent),
+        headers,
+        move |out| write_body(&mut file, out),
+    ))
+}
+
+// HEAD answers exactly as GET would, so size, validators and Vary describe
+// the representation GET selects. The server sends no body for it.
+pub fn files_handler_head(state: &AppState, req: Request) -> Result<Response, Response> {
+    files_handler(state, req)
+}
+
+pub fn files_handler_delete(state: &AppState, req: Request) -> Result<Response, Response> {
+    let filepath = resolve_file_path(state, &req)?;
+    if is_blob_path(state, &filepath) {
+        return Ok(blob_method_not_allowed());
+    }
+    let metadata = match std::fs::symlink_metadata(&filepath) {
+        Ok(metadata) => metadata,
+        Err(_) => {
+            return Ok(Response::builder(
+                Status::new(StatusCode::NotFound),
+                "404 Not Found".to_string(),
+                HashMap::new(),
+            ))
+        }
+    };
+    // Deleting a collection deletes everything in it (RFC 4918 section 9.6)
+    if metadata.is_dir() {
+        if files_sandbox(state)?.root() == filepath {
+            return Ok(Response::builder(
+                Status::new(StatusCode::Forbidden),
+                "403 Forbidden".to_string(),
+                HashMap::new(),
+            ));
+        }
+        return Ok(match std::fs::remove_dir_all(&filepath) {
+            Ok(()) => Response::builder(
+                Status::new(StatusCode::NoContent),
+                "".to_string(),
+                HashMap::new(),
+            ),
+            Err(_) => Response::builder(
+                Status::new(StatusCode::InternalServerError),
+                "500 Internal Server Error".to_string(),
+                HashMap::new(),
+            ),
+        });
+    }
+
+    let validators = match std::fs::metadata(&filepath) {
+        Ok(metadata) => files::validators(&metadata, None),
+        Err(_) => Validators::none(),
+    };
+    if conditional::evaluate(&req, &validators) != Precondition::Proceed {
+        return Ok(Response::builder(
+            Status::new(StatusCode::PreconditionFailed),
+            "".to_string(),
+            HashMap::new(),
+        ));
+    }
+
+    match std::fs::remove_file(&filepath) {
+        Ok(()) => Ok(Response::builder(
+            Status::new(StatusCode::NoContent),
+            "".to_string(),
+            HashMap::new(),
+        )),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Response::builder(
+            Status::new(StatusCode::NotFound),
+            "404 Not Found".to_string(),
+            HashMap::new(),
+        )),
+        Err(_) => Ok(Response::builder(
+            Status::new(StatusCode::InternalServerError),
+            "500 Internal Server Error".to_string(),
+            HashMap::new(),
+        )),
+    }
+}
+
+// POST creates a file and never replaces one. With Tus-Resumable it creates a
+// resumable upload of the file instead.
+pub fn files_handler_create(state: &AppState, req: Request) -> Result<Response, Response> {
+    if req.headers.contains_key(&Header::TusResumable) {
+        return tus_create(state, req);
+    }
+    files_upload(state, req, WriteMode::CreateNew)
+}
+
+// PUT creates or replaces a file
+pub fn files_handler_replace(state: &AppState, req: Request) -> Result<Response, Response> {
+    files_upload(state, req, WriteMode::Replace)
+}
+
+fn files_upload(state: &AppState, mut req: Request, mode: WriteMode) -> Result<Response, Response> {
+    let file_path = resolve_file_path(state, &req)?;
+    if is_blob_path(state, &file_path) {
+        return Ok(blob_method_not_allowed());
+    }
+    if req.is_multipart() {
+        return files_upload_multipart(state, req, &file_path, mode);
+    }
+    let expected = expected_digests(&req.headers)?;
+
+    let metadata = std::fs::metadata(&file_path).ok();
+    if metadata
+        .as_ref()
+        .map(|metadata| metadata.is_dir())
+        .unwrap_or(false)
+    {
+        return Ok(Response::builder(
+            Status::new(StatusCode::Conflict),
+            "409 Conflict".to_string(),
+            HashMap::new(),
+        ));
+    }
+
+    // Refuse to clobber a file that changed since the client last saw it
+    let validators = match &metadata {
+        Some(metadata) => files::validators(metadata, None),
+        None => Validators::none(),
+    };
+    if conditional::evaluate(&req, &validators) != Precondition::Proceed {
+        return Ok(Response::builder(
+            Status::new(StatusCode::PreconditionFailed),
+            "".to_string(),
+            HashMap::new(),
+        ));
+    }
+
+    let existed = metadata.is_some();
+    if existed && mode == WriteMode::CreateNew {
+        return Ok(Response::builder(
+            Status::new(StatusCode::Conflict),
+            "409 Conflict".to_string(),
+            HashMap::new(),
+        ));
+    }
+
+    // Create missing intermediate directories; a file in the way is a conflict
+    if let Some(parent) = file_path.parent() {
+        if parent.exists() && !parent.is_dir() || std::fs::create_dir_all(parent).is_err() {
+            return Ok(Response::builder(
+                Status::new(StatusCode::Conflict),
+                "409 Conflict".to_string(),
+                HashMap::new(),
+            ));
+        }
+    }
+
+    // Stream the request body to a temporary file, hashing it on the way, and
+    // only move it into place if it matches the digests the client sent
+    let mut algorithms: Vec<DigestAlgorithm> = expected.iter().map(|(a, _)| *a).collect();
+    algorithms.dedup();
+    let mut body = HashingReader::new(&mut req.body, &algorithms);
+    let mut mismatch = false;
+    let result = files::write_atomic_checked(&file_path, &mut body, mode, |body| {
+        let actual = body.digests();
+        if expected.iter().all(|digest| actual.contains(digest)) {
+            return Ok(());
+        }
+        mismatch = true;
+        Err(io::Error::new(ErrorKind::InvalidData, "Digest mismatch"))
+    });
+    if mismatch {
+        return Ok(digest_mismatch_response());
+    }
+    if let Err(e) = result {
+        return Ok(upload_error_response(e));
+    }
+
     let mut headers = HashMap::new();
     headers.insert(Header::ContentType, ContentType::TextPlain.to_string());
-    headers.insert(Header::ContentLength, body.len().to_string());
-    headers.extend(req.headers);
+    if let Ok(metadata) = std::fs::metadata(&file_path) {
+        insert_validators(&mut headers, &files::validators(&metadata, None));
+    }
+    if existed {
+        return Ok(Response::builder(
+            Status::new(StatusCode::NoContent),
+            "".to_string(),
+            headers,
+        ));
+    }
     Ok(Response::builder(
         Status {
-            code: StatusCode::Ok,
-            message: "OK".to_string(),
+            code: StatusCode::Created,
+            message: "Created".to_string(),
         },
-        body,
+        "".to_string(),
         headers,
     ))
 }
 
-pub fn user_agent_handler(req: Request) -> Result<Response, Response> {
-    let body = req
-        .headers
-        .get(&Header::UserAgent)
-        .cloned()
-        .unwrap_or_else(|| "No User-Agent found".to_string());
+// In content-addressed mode POST stores the body under its sha-256 digest,
+// whatever the target, and answers with the digest. Uploading content that is
+// already stored is a no-op.
+pub fn files_handler_cas_create(state: &AppState, mut req: Request) -> Result<Response, Response> {
+    let expected = expected_digests(&req.headers)?;
+    let dir = files_sandbox(state)?
+        .resolve("sha256")
+        .map_err(sandbox_error_response)?;
+
+    let mut algorithms = vec![DigestAlgorithm::Sha256];
+    algorithms.extend(expected.iter().map(|(algorithm, _)| *algorithm));
+    algorithms.sort();
+    algorithms.dedup();
+    let mut body = HashingReader::new(&mut req.body, &algorithms);
+    let mut mismatch = false;
+    let result = files::store_blob(&dir, &mut body, |digests| {
+        if expected.iter().all(|digest| digests.contains(digest)) {
+            return Ok(());
+        }
+        mismatch = true;
+        Err(io::Error::new(ErrorKind::InvalidData, "Digest mismatch"))
+    });
+    if mismatch {
+        return Ok(digest_mismatch_response());
+    }
+    let (hex, created) = match result {
+        Ok(stored) => stored,
+        Err(e) => return Ok(upload_error_response(e)),
+    };
+
     let mut headers = HashMap::new();
     headers.insert(Header::ContentType, ContentType::TextPlain.to_string());
-    headers.insert(Header::ContentLength, body.len().to_string());
-    headers.extend(req.headers);
+    headers.insert(
+        Header::Location,
+        url_for(state, "blob", &[("digest", &hex)])?,
+    );
+    let status = if created {
+        StatusCode::Created
+    } else {
+        StatusCode::Ok
+    };
+    Ok(Response::builder(Status::new(status), hex, headers))
+}
+
+// In content-addressed mode the blob directory only changes through POST, so
+// a blob always has the content its name promises
+fn is_blob_path(state: &AppState, path: &Path) -> bool {
+    match &state.config.files {
+        Some(sandbox) if state.config.content_addressed => {
+            path.starts_with(sandbox.root().join("sha256"))
+        }
+        _ => false,
+    }
+}
+
+fn blob_method_not_allowed() -> Response {
+    let mut headers = HashMap::new();
+    headers.insert(Header::Allow, "OPTIONS, GET, HEAD, PROPFIND".to_string());
+    Response::builder(
+        Status::new(StatusCode::MethodNotAllowed),
+        "405 Method Not Allowed".to_string(),
+        headers,
+    )
+}
+
+// Serves a blob stored in content-addressed mode. A blob never changes, so it
+// may be cached forever.
+pub fn files_handler_cas(state: &AppState, req: Request) -> Result<Response, Response> {
+    let hex = match req.param("digest").filter(|digest| !digest.is_empty()) {
+        Some(hex) => hex.to_lowercase(),
+        // The sha256 directory itself is an ordinary one
+        None if req.method == RequestMethod::HEAD => return files_handler_head(state, req),
+        None => return files_handler(state, req),
+    };
+    let blob = match hex::decode(&hex) {
+        Ok(digest) if digest.len() == 32 => files_sandbox(state)?
+            .resolve(&format!("sha256/{}/{}", &hex[..2], hex))
+            .map_err(sandbox_error_response)?,
+        _ => {
+            return Ok(Response::builder(
+                Status::new(StatusCode::NotFound),
+                "404 Not Found".to_string(),
+                HashMap::new(),
+            ))
+        }
+    };
+    let metadata = match std::fs::metadata(&blob) {
+        Ok(metadata) if metadata.is_file() => metadata,
+        _ => {
+            return Ok(Response::builder(
+                Status::new(StatusCode::NotFound),
+                "404 Not Found".to_string(),
+                HashMap::new(),
+            ))
+        }
+    };
+
+    let mut response = serve_file(files_sandbox(state)?, &req, &blob, &metadata);
+    if matches!(
+        response.status.code,
+        StatusCode::Ok | StatusCode::PartialContent | StatusCode::NotModified
+    ) {
+        response.headers.insert(
+            Header::CacheControl,
+            "public, max-age=31536000, immutable".to_string(),
+        );
+    }
+    Ok(response)
+}
+
+// OPTIONS advertises the tus protocol version and extensions we support, and
+// the largest upload accepted. Content-addressed POSTs store blobs instead, so
+// uploads can't be created then.
+pub fn tus_options_handler(state: &AppState, _req: Request) -> Result<Response, Response> {
+    let extensions = tus::TUS_EXTENSIONS
+        .split(',')
+        .filter(|extension| !state.config.content_addressed || *extension != "creation")
+        .collect::<Vec<_>>()
+        .join(",");
+    let mut headers = HashMap::new();
+    headers.insert(Header::TusResumable, tus::TUS_VERSION.to_string());
+    headers.insert(Header::TusVersion, tus::TUS_VERSION.to_string());
+    headers.insert(Header::TusExtension, extensions);
+    if let Some(max) = state.config.max_body_size {
+        headers.insert(Header::TusMaxSize, max.to_string());
+    }
     Ok(Response::builder(
-        Status {
-            code: StatusCode::Ok,
-            message: "OK".to_string(),
-        },
-        body,
+        Status::new(StatusCode::NoContent),
+        "".to_string(),
         headers,
     ))
 }
 
-pub fn files_handler(req: Request) -> Result<Response, Response> {
-    let file_name = req
-        .target
-        .strip_prefix("/files")
-        .and_then(|stripped| stripped.strip_prefix('/'))
-        .map(|stripped| stripped.to_string())
-        .unwrap_or_default();
-    let filepath = format!(
-        "{}{}",
-        env::args().nth(2).expect("Argument missing"),
-        file_name
+// Creates a tus upload of Upload-Length bytes that will be stored at the
+// target path, or under the `filename` from Upload-Metadata when the target
+// is a directory. The upload resource lives at /uploads/<id>.
+fn tus_create(state: &AppState, req: Request) -> Result<Response, Response> {
+    tus_check_version(&req)?;
+    let sandbox = files_sandbox(state)?;
+    let length = match req
+        .headers
+        .get(&Header::UploadLength)
+        .and_then(|length| length.trim().parse::<u64>().ok())
+    {
+        Some(length) => length,
+        None => {
+            return Ok(tus_response(
+                StatusCode::BadRequest,
+                "Invalid Upload-Length",
+            ))
+        }
+    };
+    // The whole upload is held to --max-body-size, however many PATCH
+    // requests it arrives in
+    if state.config.max_body_size.is_some_and(|max| length > max) {
+        return Ok(tus_response(
+            StatusCode::PayloadTooLarge,
+            "Upload-Length exceeds Tus-Max-Size",
+        ));
+    }
+    let metadata = req.headers.get(&Header::UploadMetadata).cloned();
+
+    let mut destination = resolve_file_path(state, &req)?;
+    if destination.is_dir() {
+        let filename = metadata
+            .as_deref()
+            .and_then(|metadata| tus::metadata_value(metadata, "filename"));
+        destination = match filename {
+            Some(filename) => sandbox
+                .join(&destination, &filename)
+                .map_err(sandbox_error_response)?,
+            None => {
+                return Ok(tus_response(
+                    StatusCode::BadRequest,
+                    "Missing filename in Upload-Metadata",
+                ))
+            }
+        };
+    }
+    if destination.exists() {
+        return Ok(tus_response(StatusCode::Conflict, "409 Conflict"));
+    }
+    // Kept as a URL path so it is resolved through the sandbox again once the
+    // upload completes
+    let relative = match sandbox.relative(&destination) {
+        Some(relative) => relative
+            .split('/')
+            .map(url::percent_encode)
+            .collect::<Vec<_>>()
+            .join("/"),
+        None => return Ok(tus_response(StatusCode::Forbidden, "403 Forbidden")),
+    };
+
+    let store = UploadStore::new(sandbox.root());
+    let mut upload = match store.create(length, relative, metadata) {
+        Ok(upload) => upload,
+        Err(e) => return Ok(tus_error_response(e)),
+    };
+    if length == 0 {
+        if let Err(e) = store.finish(&mut upload, &destination) {
+            return Ok(tus_error_response(e));
+        }
+    }
+
+    let mut headers = HashMap::new();
+    headers.insert(Header::TusResumable, tus::TUS_VERSION.to_string());
+    headers.insert(
+        Header::Location,
+        url_for(state, "upload", &[("id", &upload.id)])?,
     );
-    let body = std::fs::read_to_string(filepath);
+    if !upload.complete {
+        headers.insert(
+            Header::UploadExpires,
+            date::format_http_date(upload.expires),
+        );
+    }
+    Ok(Response::builder(
+        Status::new(StatusCode::Created),
+        "".to_string(),
+        headers,
+    ))
+}
+
+// HEAD tells a client how much of an upload arrived, so it can resume there
+pub fn tus_head_handler(state: &AppState, req: Request) -> Result<Response, Response> {
+    tus_check_version(&req)?;
+    let (_, upload) = tus_load(state, &req)?;
+
+    let mut headers = HashMap::new();
+    headers.insert(Header::TusResumable, tus::TUS_VERSION.to_string());
+    headers.insert(Header::UploadOffset, upload.offset.to_string());
+    headers.insert(Header::UploadLength, upload.length.to_string());
+    headers.insert(Header::CacheControl, "no-store".to_string());
+    if let Some(metadata) = upload.metadata {
+        headers.insert(Header::UploadMetadata, metadata);
+    }
+    if !upload.complete {
+        headers.insert(
+            Header::UploadExpires,
+            date::format_http_date(upload.expires),
+        );
+    }
+    Ok(Response::builder(
+        Status::new(StatusCode::Ok),
+        "".to_string(),
+        headers,
+    ))
+}
+
+// PATCH appends the body at Upload-Offset, which must be where the upload
+// currently ends. The last PATCH moves the file into place.
+pub fn tus_patch_handler(state: &AppState, mut req: Request) -> Result<Response, Response> {
+    tus_check_version(&req)?;
+    let content_type = req.headers.get(&Header::ContentType).map(|content_type| {
+        content_type
+            .split(';')
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_lowercase()
+    });
+    if content_type.as_deref() != Some("application/offset+octet-stream") {
+        return Ok(tus_response(
+            StatusCode::UnsupportedMediaType,
+            "Expected application/offset+octet-stream",
+        ));
+    }
+    let offset = match req
+        .headers
+        .get(&Header::UploadOffset)
+        .and_then(|offset| offset.trim().parse::<u64>().ok())
+    {
+        Some(offset) => offset,
+        None => {
+            return Ok(tus_response(
+                StatusCode::BadRequest,
+                "Invalid Upload-Offset",
+            ))
+        }
+    };
+
+    let _lock = match UploadLock::acquire(tus_upload_id(&req)) {
+        Some(lock) => lock,
+        None => return Ok(tus_response(StatusCode::Conflict, "Upload in progress")),
+    };
+    let (store, mut upload) = tus_load(state, &req)?;
+    if offset != upload.offset {
+        return Ok(tus_response(StatusCode::Conflict, "Upload-Offset mismatch"));
+    }
+    if let Some(len) = req.body.remaining() {
+        if len > upload.length - upload.offset {
+            return Ok(tus_response(
+                StatusCode::PayloadTooLarge,
+                "Body exceeds Upload-Length",
+            ));
+        }
+    }
+
+    if let Err(e) = store.append(&mut upload, &mut req.body) {
+        return Ok(tus_error_response(e));
+    }
+    if upload.offset == upload.length && !upload.complete {
+        let destination = files_sandbox(state)?
+            .resolve(&upload.destination)
+            .map_err(sandbox_error_response)?;
+        if let Err(e) = store.finish(&mut upload, &destination) {
+            return Ok(tus_error_response(e));
+        }
+    }
+
+    let mut headers = HashMap::new();
+    headers.insert(Header::TusResumable, tus::TUS_VERSION.to_string());
+    headers.insert(Header::UploadOffset, upload.offset.to_string());
+    if !upload.complete {
+        headers.insert(
+            Header::UploadExpires,
+            date::format_http_date(upload.expires),
+        );
+    }
+    Ok(Response::builder(
+        Status::new(StatusCode::NoContent),
+        "".to_string(),
+        headers,
+    ))
+}
+
+// DELETE terminates an upload and frees what was received so far
+pub fn tus_delete_handler(state: &AppState, req: Request) -> Result<Response, Response> {
+    tus_check_version(&req)?;
+    let _lock = match UploadLock::acquire(tus_upload_id(&req)) {
+        Some(lock) => lock,
+        None => return Ok(tus_response(StatusCode::Conflict, "Upload in progress")),
+    };
+    let (store, upload) = tus_load(state, &req)?;
+    if let Err(e) = store.terminate(&upload.id) {
+        return Ok(tus_error_response(e));
+    }
+    Ok(tus_response(StatusCode::NoContent, ""))
+}
+
+fn tus_upload_id(req: &Request) -> &str {
+    req.param("id").unwrap_or_default()
+}
+
+// The upload a request targets. Expired uploads are removed and reported as
+// gone.
+fn tus_load(state: &AppState, req: &Request) -> Result<(UploadStore, tus::Upload), Response> {
+    let store = UploadStore::new(files_sandbox(state)?.root());
+    let id = tus_upload_id(req);
+    match store.load(id) {
+        Ok(Some(upload)) if upload.is_expired() => {
+            let _ = store.terminate(id);
+            Err(tus_response(StatusCode::Gone, "410 Gone"))
+        }
+        Ok(Some(upload)) => Ok((store, upload)),
+        Ok(None) => Err(tus_response(StatusCode::NotFound, "404 Not Found")),
+        Err(e) => Err(tus_error_response(e)),
+    }
+}
+
+fn tus_check_version(req: &Request) -> Result<(), Response> {
+    match req.headers.get(&Header::TusResumable) {
+        Some(version) if version.trim() == tus::TUS_VERSION => Ok(()),
+        _ => {
+            let mut response = tus_response(StatusCode::PreconditionFailed, "");
+            response
+                .headers
+                .insert(Header::TusVersion, tus::TUS_VERSION.to_string());
+            Err(response)
+        }
+    }
+}
+
+fn tus_response(status: StatusCode, body: &str) -> Response {
     let mut headers = HashMap::new();
-    match body {
-        Ok(body) => {
-            headers.insert(Header::ContentLength, body.len().to_string());
+    headers.insert(Header::TusResumable, tus::TUS_VERSION.to_string());
+    Response::builder(Status::new(status), body.to_string(), headers)
+}
+
+fn tus_error_response(e: io::Error) -> Response {
+    let mut response = upload_error_response(e);
+    response
+        .headers
+        .insert(Header::TusResumable, tus::TUS_VERSION.to_string());
+    response
+}
+
+// OPTIONS on /files advertises WebDAV class 1, and tus unless POST stores
+// content-addressed blobs
+pub fn files_options_handler(state: &AppState, req: Request) -> Result<Response, Response> {
+    let mut response = if state.config.content_addressed {
+        Response::builder(
+            Status::new(StatusCode::NoContent),
+            "".to_string(),
+            HashMap::new(),
+        )
+    } else {
+        tus_options_handler(state, req)?
+    };
+    response.headers.insert(Header::Dav, "1".to_string());
+    response.headers.insert(
+        Header::Allow,
+        "OPTIONS, GET, HEAD, POST, PUT, DELETE, PROPFIND, MKCOL, COPY, MOVE".to_string(),
+    );
+    Ok(response)
+}
+
+// PROPFIND describes a file, or a directory and (with Depth: 1) its entries,
+// as a WebDAV multistatus. Depth: infinity is refused as RFC 4918 allows.
+pub fn files_handler_propfind(state: &AppState, mut req: Request) -> Result<Response, Response> {
+    let path = resolve_file_path(state, &req)?;
+    let depth = match req.headers.get(&Header::Depth).map(|depth| depth.trim()) {
+        Some("0") => 0,
+        Some("1") => 1,
+        _ => {
+            let mut headers = HashMap::new();
             headers.insert(
                 Header::ContentType,
-                ContentType::ApplicationOctetStream.to_string(),
+                "application/xml; charset=utf-8".to_string(),
             );
-            Ok(Response::builder(
-                Status {
-                    code: StatusCode::Ok,
-                    message: "OK".to_string(),
-                },
-                body,
+            return Ok(Response::builder(
+                Status::new(StatusCode::Forbidden),
+                concat!(
+                    "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n",
+                    "<D:error xmlns:D=\"DAV:\"><D:propfind-finite-depth/></D:error>\n"
+                )
+                .to_string(),
                 headers,
+            ));
+        }
+    };
+    let propfind = match webdav::parse_propfind(&req.text()?) {
+        Some(propfind) => propfind,
+        None => {
+            return Ok(Response::builder(
+                Status::new(StatusCode::BadRequest),
+                "Invalid PROPFIND body".to_string(),
+                HashMap::new(),
             ))
         }
-        Err(_) => Ok(Response::builder(
-            Status {
-                code: StatusCode::NotFound,
-                message: "Not Found".to_string(),
+    };
+    let metadata = match std::fs::metadata(&path) {
+        Ok(metadata) => metadata,
+        Err(_) => {
+            return Ok(Response::builder(
+                Status::new(StatusCode::NotFound),
+                "404 Not Found".to_string(),
+                HashMap::new(),
+            ))
+        }
+    };
+
+    let mut responses = vec![webdav::propfind_response(
+        &files_href(state, &path, metadata.is_dir())?,
+        &path,
+        &metadata,
+        &propfind,
+    )];
+    if depth == 1 && metadata.is_dir() {
+        let mut entries = listing::read_dir(&path).unwrap_or_default();
+        listing::sort(&mut entries, SortKey::Name, false);
+        for entry in entries {
+            let child = path.join(&entry.name);
+            if let Ok(metadata) = std::fs::metadata(&child) {
+                responses.push(webdav::propfind_response(
+                    &files_href(state, &child, metadata.is_dir())?,
+                    &child,
+                    &metadata,
+                    &propfind,
+                ));
+            }
+        }
+    }
+
+    let mut headers = HashMap::new();
+    headers.insert(
+        Header::ContentType,
+        "application/xml; charset=utf-8".to_string(),
+    );
+    Ok(Response::builder(
+        Status::new(StatusCode::MultiStatus),
+        webdav::multistatus(&responses),
+        headers,
+    ))
+}
+
+// MKCOL creates a single directory; its parent must already exist
+pub fn files_handler_mkcol(state: &AppState, req: Request) -> Result<Response, Response> {
+    let path = resolve_file_path(state, &req)?;
+    if is_blob_path(state, &path) {
+        return Ok(blob_method_not_allowed());
+    }
+    if !req.body.is_empty() {
+        return Ok(Response::builder(
+            Status::new(StatusCode::UnsupportedMediaType),
+            "415 Unsupported Media Type".to_string(),
+            HashMap::new(),
+        ));
+    }
+    let status = if std::fs::symlink_metadata(&path).is_ok() {
+        StatusCode::MethodNotAllowed
+    } else {
+        match std::fs::create_dir(&path) {
+            Ok(()) => StatusCode::Created,
+            Err(e) if e.kind() == ErrorKind::NotFound => StatusCode::Conflict,
+            Err(_) => match path.parent() {
+                Some(parent) if !parent.is_dir() => StatusCode::Conflict,
+                _ => StatusCode::InternalServerError,
             },
+        }
+    };
+    Ok(Response::builder(
+        Status::new(status),
+        format!("{}", status),
+        HashMap::new(),
+    ))
+}
+
+pub fn files_handler_copy(state: &AppState, req: Request) -> Result<Response, Response> {
+    files_copy_or_move(state, req, false)
+}
+
+pub fn files_handler_move(state: &AppState, req: Request) -> Result<Response, Response> {
+    files_copy_or_move(state, req, true)
+}
+
+// COPY and MOVE to the path in the Destination header, replacing what is
+// there unless `Overwrite: F`
+fn files_copy_or_move(state: &AppState, req: Request, is_move: bool) -> Result<Response, Response> {
+    let source = resolve_file_path(state, &req)?;
+    let destination = webdav_destination(state, &req)?;
+    let respond = |status: StatusCode| {
+        Ok(Response::builder(
+            Status::new(status),
+            format!("{}", status),
+            HashMap::new(),
+        ))
+    };
+
+    if is_move && is_blob_path(state, &source) {
+        return Ok(blob_method_not_allowed());
+    }
+    if is_blob_path(state, &destination) {
+        return respond(StatusCode::Forbidden);
+    }
+    if std::fs::symlink_metadata(&source).is_err() {
+        return respond(StatusCode::NotFound);
+    }
+    let root = files_sandbox(state)?.root();
+    // Nothing can be moved onto, into or out of itself, nor over a directory
+    // it is in
+    if source == root
+        || destination == root
+        || destination.starts_with(&source)
+        || source.starts_with(&destination)
+    {
+        return respond(StatusCode::Forbidden);
+    }
+    let parent = match destination.parent() {
+        Some(parent) if parent.is_dir() => parent,
+        _ => return respond(StatusCode::Conflict),
+    };
+
+    let overwrite = req
+        .headers
+        .get(&Header::Overwrite)
+        .map(|overwrite| !overwrite.trim().eq_ignore_ascii_case("F"))
+        .unwrap_or(true);
+    let existed = std::fs::symlink_metadata(&destination).is_ok();
+    if existed && !overwrite {
+        return respond(StatusCode::PreconditionFailed);
+    }
+
+    // Stage the copy or move next to the destination and only then swap it
+    // in, so a failure never loses the source or what it would replace
+    let staged = files::temp_path(parent);
+    let result = if is_move {
+        std::fs::rename(&source, &staged)
+    } else {
+        let depth = req.headers.get(&Header::Depth).map(|depth| depth.trim());
+        webdav::copy(&source, &staged, depth != Some("0"))
+    };
+    let result = result.and_then(|()| files::replace_path(&staged, &destination));
+    match result {
+        Ok(()) if existed => respond(StatusCode::NoContent),
+        Ok(()) => respond(StatusCode::Created),
+        Err(_) => {
+            if is_move {
+                let _ = std::fs::rename(&staged, &source);
+            } else {
+                let _ = files::remove_path(&staged);
+            }
+            respond(StatusCode::InternalServerError)
+        }
+    }
+}
+
+// The Destination of a COPY or MOVE, which must be another /files URL on
+// this server
+fn webdav_destination(state: &AppState, req: &Request) -> Result<PathBuf, Response> {
+    let error = |status: StatusCode| {
+        Response::builder(Status::new(status), format!("{}", status), HashMap::new())
+    };
+    let destination = match req.headers.get(&Header::Destination) {
+        Some(destination) => destination.trim(),
+        None => return Err(error(StatusCode::BadRequest)),
+    };
+    let path = match destination.split_once("://") {
+        Some((_, rest)) => {
+            let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+            let host = req.headers.get(&Header::Host).map(|host| host.trim());
+            if host
+                .map(|host| !host.eq_ignore_ascii_case(authority))
+                .unwrap_or(false)
+            {
+                return Err(error(StatusCode::BadGateway));
+            }
+            path
+        }
+        None => destination,
+    };
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    let files = url_for(state, "files", &[("path", "")])?;
+    if path != files.trim_end_matches('/') && !path.starts_with(&files) {
+        return Err(error(StatusCode::BadGateway));
+    }
+    resolve_files_url(state, path.trim_end_matches('/'))
+}
+
+// Stores every file part of a multipart/form-data body in the directory the
+// target names. Each part is streamed to a temporary file and checked against
+// the digests in its own headers; none is moved into place until all arrived
+// intact, and each is then held to the request's preconditions like a PUT.
+// Other fields are skipped.
+fn files_upload_multipart(
+    state: &AppState,
+    mut req: Request,
+    dir: &Path,
+    mode: WriteMode,
+) -> Result<Response, Response> {
+    let sandbox = files_sandbox(state)?;
+    if dir.exists() && !dir.is_dir() || std::fs::create_dir_all(dir).is_err() {
+        return Ok(Response::builder(
+            Status::new(StatusCode::Conflict),
+            "409 Conflict".to_string(),
+            HashMap::new(),
+        ));
+    }
+
+    let error_response = |status: StatusCode, message: String| {
+        let mut headers = HashMap::new();
+        headers.insert(Header::ContentType, ContentType::TextPlain.to_string());
+        Response::builder(Status::new(status), message, headers)
+    };
+    let write_error_status = |e: &io::Error| match e.kind() {
+        ErrorKind::AlreadyExists => StatusCode::Conflict,
+        ErrorKind::FileTooLarge => StatusCode::PayloadTooLarge,
+        ErrorKind::UnexpectedEof | ErrorKind::InvalidData => StatusCode::BadRequest,
+        _ => StatusCode::InternalServerError,
+    };
+
+    let mut multipart = req
+        .multipart()
+        .map_err(|e| error_response(StatusCode::BadRequest, e.to_string()))?;
+    let mut staged = Vec::new();
+    loop {
+        let mut part = match multipart.next_part() {
+            Ok(Some(part)) => part,
+            Ok(None) => break,
+            Err(e) if e.kind() == ErrorKind::FileTooLarge => {
+                return Ok(error_response(StatusCode::PayloadTooLarge, e.to_string()))
+            }
+            Err(e) => return Ok(error_response(StatusCode::BadRequest, e.to_string())),
+        };
+        // Browsers may send a client-side path; only the last component counts
+        let file_name = match &part.filename {
+            Some(filename) => filename
+                .rsplit(['/', '\\'])
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+            None => continue,
+        };
+        if file_name.is_empty() {
+            continue;
+        }
+        let file_path = sandbox
+            .join(dir, &file_name)
+            .map_err(sandbox_error_response)?;
+        let expected = expected_digests(&part.headers)?;
+
+        let mut algorithms: Vec<DigestAlgorithm> = expected.iter().map(|(a, _)| *a).collect();
+        algorithms.dedup();
+        let mut body = HashingReader::new(&mut part, &algorithms);
+        let mut mismatch = false;
+        let result = files::stage(&file_path, &mut body, |body| {
+            let actual = body.digests();
+            if expected.iter().all(|digest| actual.contains(digest)) {
+                return Ok(());
+            }
+            mismatch = true;
+            Err(io::Error::new(ErrorKind::InvalidData, "Digest mismatch"))
+        });
+        if mismatch {
+            return Ok(digest_mismatch_response());
+        }
+        match result {
+            Ok((file, _)) => staged.push((file_name, file)),
+            Err(e) => {
+                let status = write_error_status(&e);
+                return Ok(error_response(status, format!("{}: {}", file_name, status)));
+            }
+        }
+    }
+
+    for (file_name, file) in &staged {
+        let metadata = std::fs::metadata(file.path()).ok();
+        let validators = match &metadata {
+            Some(metadata) if metadata.is_dir() => {
+                let status = StatusCode::Conflict;
+                return Ok(error_response(status, format!("{}: {}", file_name, status)));
+            }
+            Some(metadata) => files::validators(metadata, None),
+            None => Validators::none(),
+        };
+        if conditional::evaluate(&req, &validators) != Precondition::Proceed {
+            let status = StatusCode::PreconditionFailed;
+            return Ok(error_response(status, format!("{}: {}", file_name, status)));
+        }
+    }
+
+    // Files this request created are removed again if a later part can't be
+    // moved into place; replaced files can't be restored
+    let mut created: Vec<PathBuf> = Vec::new();
+    let mut stored = Vec::new();
+    for (file_name, file) in staged {
+        let path = file.path().to_path_buf();
+        let existed = std::fs::symlink_metadata(&path).is_ok();
+        if let Err(e) = file.commit(mode) {
+            for path in &created {
+                let _ = std::fs::remove_file(path);
+            }
+            let status = write_error_status(&e);
+            return Ok(error_response(status, format!("{}: {}", file_name, status)));
+        }
+        if !existed {
+            created.push(path.clone());
+        }
+        stored.push(sandbox.relative(&path).unwrap_or(file_name));
+    }
+
+    let mut body = stored.join("\n");
+    if !body.is_empty() {
+        body.push('\n');
+    }
+    // Like a single-body PUT, replacing files only isn't a creation; the body
+    // still lists what was stored, so 200 stands in for 204
+    let status = if created.is_empty() {
+        StatusCode::Ok
+    } else {
+        StatusCode::Created
+    };
+    let mut headers = HashMap::new();
+    headers.insert(Header::ContentType, ContentType::TextPlain.to_string());
+    Ok(Response::builder(Status::new(status), body, headers))
+}
+
+// Maps the part of the target after `/files/` into the configured files
+// directory, refusing anything that would escape it.
+fn files_sandbox(state: &AppState) -> Result<&Sandbox, Response> {
+    match &state.config.files {
+        Some(sandbox) => Ok(sandbox),
+        None => Err(Response::builder(
+            Status::new(StatusCode::NotFound),
             "404 Not Found".to_string(),
             HashMap::new(),
         )),
     }
 }
 
-pub fn files_handler_create(req: Request) -> Result<Response, Response> {
-    let file_name = req
-        .target
-        .strip_prefix("/files")
-        .and_then(|stripped| stripped.strip_prefix('/'))
-        .map(|stripped| stripped.to_string())
+fn resolve_file_path(state: &AppState, req: &Request) -> Result<PathBuf, Response> {
+    resolve_files_url(state, req.path())
+}
+
+// Maps a /files URL path to a path in the files directory
+fn resolve_files_url(state: &AppState, url_path: &str) -> Result<PathBuf, Response> {
+    let files = url_for(state, "files", &[("path", "")])?;
+    let file_name = url_path
+        .strip_prefix(files.trim_end_matches('/'))
+        .map(|stripped| stripped.strip_prefix('/').unwrap_or(stripped))
         .unwrap_or_default();
-    let file = format!(
-        "{}/{}",
-        env::args().nth(2).expect("Argument missing"),
-        file_name
-    );
-    let file_path = Path::new(&file);
 
-    // Write the request body to the file
-    if let Some(body) = &req.body {
-        if let Err(_) = std::fs::write(file_path, body) {
-            return Ok(Response::builder(
-                Status {
-                    code: StatusCode::InternalServerError,
-                    message: "Internal Server Error".to_string(),
-                },
+    let sandbox = files_sandbox(state)?;
+
+    let path = sandbox.resolve(file_name).map_err(sandbox_error_response)?;
+    // Resumable upload state is neither served nor writable through /files
+    if path.starts_with(sandbox.root().join(tus::STATE_DIR)) {
+        return Err(Response::builder(
+            Status::new(StatusCode::NotFound),
+            "404 Not Found".to_string(),
+            HashMap::new(),
+        ));
+    }
+    Ok(path)
+}
+
+fn sandbox_error_response(e: SandboxError) -> Response {
+    let status = match e {
+        SandboxError::InvalidEncoding => StatusCode::BadRequest,
+        SandboxError::AbsolutePath | SandboxError::Escape | SandboxError::Symlink => {
+            StatusCode::Forbidden
+        }
+        SandboxError::Io(_) => StatusCode::InternalServerError,
+    };
+    let mut headers = HashMap::new();
+    headers.insert(Header::ContentType, ContentType::TextPlain.to_string());
+    Response::builder(Status::new(status), e.to_string(), headers)
+}
+
+// The configured index file of `dir`, if there is one
+fn directory_index(state: &AppState, dir: &Path) -> Result<Option<PathBuf>, Response> {
+    let (sandbox, index_file) = match (&state.config.files, &state.config.index_file) {
+        (Some(sandbox), Some(index_file)) => (sandbox, index_file),
+        _ => return Ok(None),
+    };
+    let index = sandbox
+        .join(dir, index_file)
+        .map_err(sandbox_error_response)?;
+    Ok(Some(index).filter(|index| index.is_file()))
+}
+
+// Lists a directory as JSON when the client asks for it, HTML otherwise.
+// `?sort=name|size|mtime` and `?order=asc|desc` control the ordering.
+fn files_listing_response(state: &AppState, req: &Request, dir: &Path) -> Response {
+    let mut entries = match listing::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => {
+            return Response::builder(
+                Status::new(StatusCode::InternalServerError),
                 "500 Internal Server Error".to_string(),
                 HashMap::new(),
-            ));
+            )
         }
+    };
+    let sort_key = req
+        .query_param("sort")
+        .and_then(|key| SortKey::from_string(&key))
+        .unwrap_or(SortKey::Name);
+    let descending = req.query_param("order").as_deref() == Some("desc");
+    listing::sort(&mut entries, sort_key, descending);
+
+    let wants_json = req
+        .headers
+        .get(&Header::Accept)
+        .map(|accept| accept.contains("application/json"))
+        .unwrap_or(false);
+
+    let mut headers = HashMap::new();
+    headers.insert(Header::Vary, "Accept".to_string());
+    let body = if wants_json {
+        headers.insert(
+            Header::ContentType,
+            ContentType::ApplicationJson.to_string(),
+        );
+        listing::to_json(&entries)
+    } else {
+        let relative = state
+            .config
+            .files
+            .as_ref()
+            .and_then(|sandbox| sandbox.relative(dir))
+            .unwrap_or_default();
+        let base = match files_href(state, dir, true) {
+            Ok(base) => base,
+            Err(response) => return response,
+        };
+        headers.insert(
+            Header::ContentType,
+            format!("{}; charset=utf-8", ContentType::TextHtml),
+        );
+        listing::to_html(&entries, &base, !relative.is_empty())
+    };
+    headers.insert(Header::ContentLength, body.len().to_string());
+    Response::builder(Status::new(StatusCode::Ok), body, headers)
+}
+
+// The /files URL of a path in the files directory; directories get a
+// trailing slash
+fn files_href(state: &AppState, path: &Path, is_dir: bool) -> Result<String, Response> {
+    let relative = state
+        .config
+        .files
+        .as_ref()
+        .and_then(|sandbox| sandbox.relative(path))
+        .unwrap_or_default();
+    let mut href = url_for(state, "files", &[("path", &relative)])?;
+    if is_dir && !href.ends_with('/') {
+        href.push('/');
     }
+    Ok(href)
+}
 
+// The URL of a named route. Not finding the route is a bug in the server.
+fn url_for(state: &AppState, name: &str, params: &[(&str, &str)]) -> Result<String, Response> {
+    state.urls.url_for(name, params).map_err(|e| {
+        Response::builder(
+            Status::new(StatusCode::InternalServerError),
+            format!("500 Internal Server Error: {}", e),
+            HashMap::new(),
+        )
+    })
+}
+
+fn upload_error_response(e: io::Error) -> Response {
+    let status = match e.kind() {
+        ErrorKind::AlreadyExists => StatusCode::Conflict,
+        ErrorKind::FileTooLarge => StatusCode::PayloadTooLarge,
+        ErrorKind::UnexpectedEof | ErrorKind::InvalidData => StatusCode::BadRequest,
+        _ => StatusCode::InternalServerError,
+    };
+    Response::builder(Status::new(status), format!("{}", status), HashMap::new())
+}
+
+// The body didn't match the Content-Digest or Repr-Digest sent with it
+fn digest_mismatch_response() -> Response {
     let mut headers = HashMap::new();
-    headers.insert(Header::ContentType, ContentType::TextPlain.to_string());
-    Ok(Response::builder(
-        Status {
-            code: StatusCode::Created,
-            message: "Created".to_string(),
-        },
-        "".to_string(),
+    headers.insert(Header::WantContentDigest, "sha-256=1".to_string());
+    Response::builder(
+        Status::new(StatusCode::BadRequest),
+        "Digest mismatch".to_string(),
         headers,
-    ))
-}
\ No newline at end of file
+    )
+}
+
+// The algorithm to digest a response with, if the client asked for a digest
+// with the matching `Want-*` header. Digests mean hashing the whole body, so
+// none are sent unasked.
+fn digest_algorithm(req: &Request, want: &Header) -> Option<DigestAlgorithm> {
+    digest::preferred_algorithm(req.headers.get(want)?)
+}
+
+// Adds Repr-Digest for the whole identity file when a partial or bodiless
+// response doesn't carry it
+fn insert_repr_digest(req: &Request, headers: &mut HashMap<Header, String>, filepath: &Path) {
+    if let Some(algorithm) = digest_algorithm(req, &Header::WantReprDigest) {
+        if let Ok(digest) = File::open(filepath).and_then(|file| algorithm.digest_reader(file)) {
+            headers.insert(
+                Header::ReprDigest,
+                digest::format_digest_field(&[(algorithm, digest)]),
+            );
+        }
+    }
+}
+
+// The digests a client sent along with an upload, or with one part of a
+// multipart upload. Content-Digest and Repr-Digest both cover the stored
+// bytes, as uploads are stored as sent.
+fn expected_digests(
+    headers: &HashMap<Header, String>,
+) -> Result<Vec<(DigestAlgorithm, Vec<u8>)>, Response> {
+    let mut expected = Vec::new();
+    for header in [Header::ContentDigest, Header::ReprDigest] {
+        if let Some(value) = headers.get(&header) {
+            match digest::parse_digest_field(value) {
+                Some(digests) => expected.extend(digests),
+                None => {
+                    return Err(Response::builder(
+                        Status::new(StatusCode::BadRequest),
+                        format!("Invalid {}", header),
+                        HashMap::new(),
+                    ))
+                }
+            }
+        }
+    }
+    expected.sort_by_key(|(algorithm, _)| *algorithm);
+    Ok(expected)
+}
+
+fn insert_validators(headers: &mut HashMap<Header, String>, validators: &Validators) {
+    if let Some(etag) = &validators.etag {
+        headers.insert(Header::ETag, etag.clone());
+    }
+    if let Some(last_modified) = validators.last_modified {
+        headers.insert(Header::LastModified, date::format_http_date(last_modified));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::body::Body;
+    use std::io::Cursor;
+
+    fn state() -> AppState {
+        let config = Config::from_args(Vec::new());
+        let urls = router(&config).urls();
+        AppState::new(config, urls)
+    }
+
+    fn post(content_type: &str, body: &str) -> Request {
+        let mut headers = HashMap::new();
+        headers.insert(Header::ContentType, content_type.to_string());
+        let body = body.as_bytes().to_vec();
+        let len = body.len() as u64;
+        Request {
+            method: RequestMethod::POST,
+            target: "/echo".to_string(),
+            version: "HTTP/1.1".to_string(),
+            body: Body::with_length(Box::new(Cursor::new(body)), len),
+            headers,
+            params: Vec::new(),
+        }
+    }
+
+    fn echo(content_type: &str, body: &str) -> Result<String, StatusCode> {
+        match echo_body_handler(&state(), post(content_type, body)) {
+            Ok(extract::Json(value)) => Ok(value.to_string()),
+            Err(response) => Err(response.status.code),
+        }
+    }
+
+    #[test]
+    fn echoes_json_bodies() {
+        assert_eq!(
+            echo(
+                "application/json",
+                r#" {"a": [1, 2.5, null], "b": "\u00e9"} "#
+            ),
+            Ok(r#"{"a":[1,2.5,null],"b":"é"}"#.to_string())
+        );
+        assert_eq!(
+            echo("application/problem+json; charset=utf-8", "true"),
+            Ok("true".to_string())
+        );
+    }
+
+    #[test]
+    fn echoes_forms_as_json() {
+        assert_eq!(
+            echo("application/x-www-form-urlencoded", "a=1&b=x+y&a=%262"),
+            Ok(r#"{"a":["1","&2"],"b":["x y"]}"#.to_string())
+        );
+    }
+
+    #[test]
+    fn refuses_other_or_malformed_bodies() {
+        assert_eq!(
+            echo("text/plain", "{}"),
+            Err(StatusCode::UnsupportedMediaType)
+        );
+        assert_eq!(
+            echo("application/json", "{\"a\":}"),
+            Err(StatusCode::BadRequest)
+        );
+        assert_eq!(
+            echo("application/json", "\u{ff}"),
+            Err(StatusCode::BadRequest)
+        );
+    }
+
+    fn file_request(method: RequestMethod, path: &str, accept_encoding: &str) -> Request {
+        let mut headers = HashMap::new();
+        headers.insert(Header::AcceptEncoding, accept_encoding.to_string());
+        Request {
+            method,
+            target: path.to_string(),
+            version: "HTTP/1.1".to_string(),
+            body: Body::empty(),
+            headers,
+            params: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn head_describes_the_representation_get_sends() {
+        let dir = std::env::temp_dir().join(format!("routes-head-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.txt"), "plain text").unwrap();
+        std::fs::write(dir.join("a.txt.gz"), "gz").unwrap();
+        let args = ["server", "--directory", dir.to_str().unwrap()];
+        let config = Config::from_args(args.iter().map(|arg| arg.to_string()));
+        let urls = router(&config).urls();
+        let state = AppState::new(config, urls);
+
+        for (accept_encoding, len, coding) in [("", "10", None), ("gzip", "2", Some("gzip"))] {
+            let get = files_handler(
+                &state,
+                file_request(RequestMethod::GET, "/files/a.txt", accept_encoding),
+            )
+            .unwrap_or_else(|response| response);
+            let head = files_handler_head(
+                &state,
+                file_request(RequestMethod::HEAD, "/files/a.txt", accept_encoding),
+            )
+            .unwrap_or_else(|response| response);
+            assert_eq!(head.status.code, StatusCode::Ok);
+            for header in [
+                Header::ETag,
+                Header::ContentLength,
+                Header::ContentEncoding,
+                Header::Vary,
+            ] {
+                assert_eq!(
+                    head.headers.get(&header),
+                    get.headers.get(&header),
+                    "{}",
+                    header
+                );
+            }
+            assert_eq!(head.headers.get(&Header::ContentLength).unwrap(), len);
+            assert_eq!(
+                head.headers
+                    .get(&Header::ContentEncoding)
+                    .map(String::as_str),
+                coding
+            );
+            assert_eq!(head.headers.get(&Header::Vary).unwrap(), "Accept-Encoding");
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn multipart_put_answers_created_only_for_new_files() {
+        let dir = std::env::temp_dir().join(format!("routes-multipart-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let args = ["server", "--directory", dir.to_str().unwrap()];
+        let config = Config::from_args(args.iter().map(|arg| arg.to_string()));
+        let urls = router(&config).urls();
+        let state = AppState::new(config, urls);
+
+        let put = |content: &str| {
+            let body = format!(
+                "--XX\r\nContent-Disposition: form-data; name=\"f\"; filename=\"a.txt\"\r\n\r\n\
+                 {}\r\n--XX--\r\n",
+                content
+            );
+            let mut req = post("multipart/form-data; boundary=XX", &body);
+            req.method = RequestMethod::PUT;
+            req.target = "/files/up".to_string();
+            let response = files_handler_replace(&state, req).unwrap_or_else(|response| response);
+            (
+                response.status.code,
+                String::from_utf8(response.body.clone()).unwrap(),
+            )
+        };
+        assert_eq!(put("one"), (StatusCode::Created, "up/a.txt\n".to_string()));
+        assert_eq!(put("two"), (StatusCode::Ok, "up/a.txt\n".to_string()));
+        assert_eq!(
+            std::fs::read_to_string(dir.join("up/a.txt")).unwrap(),
+            "two"
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn options_advertise_tus_creation_only_where_posts_create_uploads() {
+        for (args, files_tus, extensions) in [
+            (vec!["server"], true, "creation,termination,expiration"),
+            (vec!["server", "--cas"], false, "termination,expiration"),
+        ] {
+            let config = Config::from_args(args.iter().map(|arg| arg.to_string()));
+            let urls = router(&config).urls();
+            let state = AppState::new(config, urls);
+
+            let files =
+                files_options_handler(&state, file_request(RequestMethod::OPTIONS, "/files/", ""))
+                    .unwrap_or_else(|response| response);
+            assert_eq!(files.headers.contains_key(&Header::TusResumable), files_tus);
+            assert_eq!(files.headers.contains_key(&Header::TusExtension), files_tus);
+            assert_eq!(files.headers.get(&Header::Dav).unwrap(), "1");
+
+            let uploads = tus_options_handler(
+                &state,
+                file_request(RequestMethod::OPTIONS, "/uploads/x", ""),
+            )
+            .unwrap_or_else(|response| response);
+            assert_eq!(
+                uploads.headers.get(&Header::TusExtension).unwrap(),
+                extensions
+            );
+        }
+    }
+}
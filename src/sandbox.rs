@@ -0,0 +1,306 @@
+use crate::url::percent_decode;
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    // Follow symlinks as long as their target stays inside the root
+    FollowWithinRoot,
+    // Refuse any path that goes through a symlink
+    Deny,
+    // Follow symlinks wherever they point
+    Allow,
+}
+
+impl SymlinkPolicy {
+    pub fn from_string(policy: &str) -> Result<Self, io::Error> {
+        match policy {
+            "follow" => Ok(SymlinkPolicy::FollowWithinRoot),
+            "deny" => Ok(SymlinkPolicy::Deny),
+            "allow" => Ok(SymlinkPolicy::Allow),
+            _ => Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid symlink policy: {}", policy),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SymlinkPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SymlinkPolicy::FollowWithinRoot => write!(f, "follow"),
+            SymlinkPolicy::Deny => write!(f, "deny"),
+            SymlinkPolicy::Allow => write!(f, "allow"),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SandboxError {
+    #[error("invalid percent-encoding in path")]
+    InvalidEncoding,
+    #[error("absolute paths are not allowed")]
+    AbsolutePath,
+    #[error("path escapes the served directory")]
+    Escape,
+    #[error("path goes through a symbolic link")]
+    Symlink,
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+// A directory that URL paths are resolved into. Resolution never yields a
+// path outside `root`, except through symlinks when the policy is `Allow`.
+pub struct Sandbox {
+    root: PathBuf,
+    symlinks: SymlinkPolicy,
+}
+
+impl Sandbox {
+    pub fn new<P: AsRef<Path>>(root: P, symlinks: SymlinkPolicy) -> Result<Self, io::Error> {
+        let root = root.as_ref().canonicalize()?;
+        if !root.is_dir() {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "Not a directory"));
+        }
+        Ok(Sandbox { root, symlinks })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    // Maps a percent-encoded, `/`-separated path relative to the root onto the
+    // filesystem. The target itself does not need to exist.
+    pub fn resolve(&self, url_path: &str) -> Result<PathBuf, SandboxError> {
+        let decoded = percent_decode(url_path).map_err(|_| SandboxError::InvalidEncoding)?;
+        if decoded.contains('\0') {
+            return Err(SandboxError::InvalidEncoding);
+        }
+        if decoded.starts_with('/') || decoded.starts_with('\\') {
+            return Err(SandboxError::AbsolutePath);
+        }
+
+        let mut segments: Vec<&str> = Vec::new();
+        for segment in decoded.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => {
+                    if segments.pop().is_none() {
+                        return Err(SandboxError::Escape);
+                    }
+                }
+                // A backslash or drive prefix would be a separator on Windows;
+                // never let it through as part of a name
+                _ if segment.contains('\\') || (cfg!(windows) && segment.contains(':')) => {
+                    return Err(SandboxError::Escape);
+                }
+                _ => segments.push(segment),
+            }
+        }
+
+        let mut path = self.root.clone();
+        for segment in segments {
+            path.push(segment);
+            self.check_symlink(&path)?;
+        }
+        Ok(path)
+    }
+
+    // Joins a single, already decoded file name onto a resolved directory
+    pub fn join(&self, dir: &Path, name: &str) -> Result<PathBuf, SandboxError> {
+        if name.is_empty()
+            || name == "."
+            || name == ".."
+            || name.contains('/')
+            || name.contains('\\')
+        {
+            return Err(SandboxError::Escape);
+        }
+        if name.contains('\0') {
+            return Err(SandboxError::InvalidEncoding);
+        }
+        if !dir.starts_with(&self.root) {
+            return Err(SandboxError::Escape);
+        }
+        let path = dir.join(name);
+        self.check_symlink(&path)?;
+        Ok(path)
+    }
+
+    // Returns the path of `path` relative to the root, `/`-separated
+    pub fn relative(&self, path: &Path) -> Option<String> {
+        let relative = path.strip_prefix(&self.root).ok()?;
+        let segments: Vec<String> = relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        Some(segments.join("/"))
+    }
+
+    fn check_symlink(&self, path: &Path) -> Result<(), SandboxError> {
+        // Missing paths (or a file where a directory was expected) can't be
+        // symlinks; whatever operation comes next reports the error
+        let metadata = match path.symlink_metadata() {
+            Ok(metadata) => metadata,
+            Err(_) => return Ok(()),
+        };
+        if !metadata.file_type().is_symlink() {
+            return Ok(());
+        }
+        match self.symlinks {
+            SymlinkPolicy::Allow => Ok(()),
+            SymlinkPolicy::Deny => Err(SandboxError::Symlink),
+            SymlinkPolicy::FollowWithinRoot => match path.canonicalize() {
+                Ok(target) if target.starts_with(&self.root) => Ok(()),
+                Ok(_) => Err(SandboxError::Escape),
+                // Dangling link: nothing to read, but don't let writes follow it
+                Err(_) => Err(SandboxError::Symlink),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    // `root/` holding a file and links to it and to `outside/`, a sibling
+    fn tree(test: &str) -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("sandbox-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let (root, outside) = (dir.join("root"), dir.join("outside"));
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        std::fs::write(root.join("sub/a.txt"), "in").unwrap();
+        std::fs::write(outside.join("secret.txt"), "out").unwrap();
+        symlink(root.join("sub"), root.join("inside-link")).unwrap();
+        symlink(&outside, root.join("outside-link")).unwrap();
+        (dir, root)
+    }
+
+    fn sandbox(root: &Path, symlinks: SymlinkPolicy) -> Sandbox {
+        Sandbox::new(root, symlinks).unwrap()
+    }
+
+    #[test]
+    fn resolves_inside_the_root() {
+        let (dir, root) = tree("inside");
+        let sandbox = sandbox(&root, SymlinkPolicy::Deny);
+        let root = sandbox.root().to_path_buf();
+        assert_eq!(
+            sandbox.resolve("sub/a.txt").unwrap(),
+            root.join("sub/a.txt")
+        );
+        assert_eq!(
+            sandbox.resolve("./sub//a.txt").unwrap(),
+            root.join("sub/a.txt")
+        );
+        assert_eq!(
+            sandbox.resolve("sub/../sub/a.txt").unwrap(),
+            root.join("sub/a.txt")
+        );
+        assert_eq!(sandbox.resolve("a%20b.txt").unwrap(), root.join("a b.txt"));
+        assert_eq!(sandbox.resolve("").unwrap(), root);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_escapes() {
+        let (dir, root) = tree("escapes");
+        let sandbox = sandbox(&root, SymlinkPolicy::Allow);
+        for path in [
+            "..",
+            "../outside/secret.txt",
+            "sub/../../outside",
+            "%2e%2e/outside",
+            "%2E%2E%2foutside",
+            "sub%2f..%2f..%2foutside",
+            "sub\\..\\..\\outside",
+            "..%5coutside",
+        ] {
+            assert!(
+                matches!(sandbox.resolve(path), Err(SandboxError::Escape)),
+                "{} should escape",
+                path
+            );
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_absolute_paths_and_bad_encodings() {
+        let (dir, root) = tree("absolute");
+        let sandbox = sandbox(&root, SymlinkPolicy::Allow);
+        for path in ["/etc/passwd", "%2fetc/passwd", "\\windows", "%5cwindows"] {
+            assert!(matches!(
+                sandbox.resolve(path),
+                Err(SandboxError::AbsolutePath)
+            ));
+        }
+        for path in ["a%00.txt", "sub/%00", "%zz", "%4", "%ff"] {
+            assert!(matches!(
+                sandbox.resolve(path),
+                Err(SandboxError::InvalidEncoding)
+            ));
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn symlink_policies() {
+        let (dir, root) = tree("symlinks");
+        let follow = sandbox(&root, SymlinkPolicy::FollowWithinRoot);
+        assert!(follow.resolve("inside-link/a.txt").is_ok());
+        assert!(matches!(
+            follow.resolve("outside-link/secret.txt"),
+            Err(SandboxError::Escape)
+        ));
+
+        let deny = sandbox(&root, SymlinkPolicy::Deny);
+        assert!(matches!(
+            deny.resolve("inside-link/a.txt"),
+            Err(SandboxError::Symlink)
+        ));
+        assert!(matches!(
+            deny.resolve("outside-link"),
+            Err(SandboxError::Symlink)
+        ));
+
+        let allow = sandbox(&root, SymlinkPolicy::Allow);
+        assert!(allow.resolve("inside-link/a.txt").is_ok());
+        let secret = allow.resolve("outside-link/secret.txt").unwrap();
+        assert_eq!(std::fs::read_to_string(secret).unwrap(), "out");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn joins_single_names() {
+        let (dir, root) = tree("join");
+        let sandbox = sandbox(&root, SymlinkPolicy::Deny);
+        let sub = sandbox.resolve("sub").unwrap();
+        assert_eq!(sandbox.join(&sub, "b.txt").unwrap(), sub.join("b.txt"));
+        for name in ["", ".", "..", "a/b", "a\\b"] {
+            assert!(matches!(
+                sandbox.join(&sub, name),
+                Err(SandboxError::Escape)
+            ));
+        }
+        assert!(matches!(
+            sandbox.join(&sub, "a\0"),
+            Err(SandboxError::InvalidEncoding)
+        ));
+        assert!(matches!(
+            sandbox.join(Path::new("/tmp"), "a"),
+            Err(SandboxError::Escape)
+        ));
+        assert_eq!(
+            sandbox.relative(&sub.join("b.txt")).as_deref(),
+            Some("sub/b.txt")
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
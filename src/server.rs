@@ -1,41 +1,308 @@
-use crate::http::{Status, StatusCode};
+use crate::error_pages::ErrorPages;
+use crate::http::{Header, RequestMethod, Status, StatusCode};
 use crate::request::Request;
 use crate::response::Response;
 use crate::router::Router;
+use crate::vhost::{self, HostPattern, VirtualHost};
 use std::collections::HashMap;
+use std::future::Future;
+use std::io;
 use std::net::TcpStream;
-use std::sync::{Arc, RwLock};
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tokio::task;
 
-pub type RequestHandler = Arc<dyn Fn(Request) -> Result<Response, Response> + Send + Sync>;
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
 
-pub struct HttpServer {
-    router: Arc<RwLock<Router>>,
+pub type RequestHandler<S> = Arc<
+    dyn Fn(Arc<S>, Request) -> BoxFuture<'static, Result<Response, Response>> + Send + Sync,
+>;
+
+// Answers a request that couldn't be parsed
+pub type ParseErrorHandler = Box<dyn Fn(&io::Error) -> Response + Send + Sync>;
+
+pub struct HttpServer<S> {
+    // Answers requests for names no virtual host is for, and those without
+    // a Host
+    default_host: VirtualHost<S>,
+    hosts: Vec<(HostPattern, VirtualHost<S>)>,
+    // Requests declaring a larger body are refused with 413 before it is
+    // read, and reading a chunked one fails once it gets larger
+    max_body_size: Option<u64>,
+    on_parse_error: ParseErrorHandler,
+    error_pages: Option<ErrorPages>,
 }
 
-impl HttpServer {
-    pub fn new(router: Arc<RwLock<Router>>) -> Self {
-        HttpServer { router }
+impl<S: Send + Sync + 'static> HttpServer<S> {
+    pub fn new(router: Arc<RwLock<Router<S>>>, state: S) -> Self {
+        HttpServer {
+            default_host: VirtualHost {
+                router,
+                state: Arc::new(state),
+            },
+            hosts: Vec::new(),
+            max_body_size: None,
+            on_parse_error: Box::new(|_| {
+                Response::builder(
+                    Status::new(StatusCode::BadRequest),
+                    "400 Bad Request".to_string(),
+                    HashMap::new(),
+                )
+            }),
+            error_pages: None,
+        }
     }
 
-    pub fn handle_client(&self, mut stream: TcpStream) {
-        let req = match Request::builder(&stream) {
+    pub fn max_body_size(mut self, max_body_size: Option<u64>) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    // Serves requests whose Host matches `pattern` with their own router and
+    // state. `pattern` is a name, or `*.name` for all of its subdomains.
+    pub fn virtual_host(mut self, pattern: &str, router: Router<S>, state: S) -> Self {
+        let host = VirtualHost {
+            router: Arc::new(RwLock::new(router)),
+            state: Arc::new(state),
+        };
+        self.hosts.push((HostPattern::parse(pattern), host));
+        self
+    }
+
+    pub fn on_parse_error<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&io::Error) -> Response + Send + Sync + 'static,
+    {
+        self.on_parse_error = Box::new(handler);
+        self
+    }
+
+    // Custom bodies for error responses, whichever part of the server made them
+    pub fn error_pages(mut self, error_pages: Option<ErrorPages>) -> Self {
+        self.error_pages = error_pages;
+        self
+    }
+
+    // Reading the request head and sending the response block, so they run on
+    // tokio's blocking threads; handlers are awaited in between.
+    pub async fn handle_client(&self, stream: tokio::net::TcpStream) {
+        let stream = match stream.into_std() {
+            Ok(stream) => stream,
+            Err(e) => return eprintln!("Error: {}", e),
+        };
+        let parsed = task::spawn_blocking(move || {
+            stream.set_nonblocking(false)?;
+            let req = Request::builder(&stream);
+            Ok::<_, io::Error>((req, stream))
+        })
+        .await;
+        let (req, stream) = match parsed {
+            Ok(Ok(parsed)) => parsed,
+            Ok(Err(e)) => return eprintln!("Error: {}", e),
+            Err(e) => return eprintln!("Error: {}", e),
+        };
+
+        let mut req = match req {
             Ok(req) => req,
-            Err(_) => {
+            Err(e) => {
+                let response = self.error_page((self.on_parse_error)(&e), None);
+                return send(response, stream).await;
+            }
+        };
+
+        let accept = req.headers.get(&Header::Accept).cloned();
+        // RFC 9112 requires a Host in every HTTP/1.1 request
+        let host = match req.headers.get(&Header::Host) {
+            Some(host) => vhost::select(&self.hosts, host).unwrap_or(&self.default_host),
+            None if req.version == "HTTP/1.1" => {
                 let response = Response::builder(
-                    Status::new(StatusCode::NotFound),
-                    "Bad Request".to_string(),
+                    Status::new(StatusCode::BadRequest),
+                    "400 Bad Request: missing Host".to_string(),
                     HashMap::new(),
                 );
-                response.send(&mut stream).unwrap();
-                return;
+                return send(self.error_page(response, accept), stream).await;
             }
+            None => &self.default_host,
         };
+        let router = host.router.read().await;
+
+        if let Some(response) = self.check_expectations(&router, &mut req, &stream) {
+            return send(self.error_page(response, accept), stream).await;
+        }
 
-        let response = match self.router.read().unwrap().route(req) {
+        let head = req.method == RequestMethod::HEAD;
+        let close_delimited = req.version == "HTTP/1.0";
+        let mut response = match router.route(&host.state, req).await {
             Ok(response) => response,
             Err(response) => response,
         };
+        response.head = head;
+        response.close_delimited = close_delimited;
+
+        send(self.error_page(response, accept), stream).await;
+    }
+
+    fn error_page(&self, mut response: Response, accept: Option<String>) -> Response {
+        if let Some(error_pages) = &self.error_pages {
+            error_pages.apply(&mut response, accept.as_deref());
+        }
+        response
+    }
+
+    // Decides whether the body may be sent before any of it is read. Returns
+    // the final response when it may not; otherwise, for `Expect: 100-continue`,
+    // arranges for `100 Continue` once the handler starts reading the body.
+    fn check_expectations(
+        &self,
+        router: &Router<S>,
+        req: &mut Request,
+        stream: &TcpStream,
+    ) -> Option<Response> {
+        if let Some(max) = self.max_body_size {
+            if req.body.remaining().unwrap_or(0) > max {
+                return Some(Response::builder(
+                    Status::new(StatusCode::PayloadTooLarge),
+                    "413 Payload Too Large".to_string(),
+                    HashMap::new(),
+                ));
+            }
+            // A chunked body's size is only known as it is read
+            req.body.limit(max);
+        }
+
+        let expect = match req.headers.get(&Header::Expect) {
+            Some(expect) => expect.trim().to_lowercase(),
+            None => return None,
+        };
+        if expect != "100-continue" {
+            return Some(Response::builder(
+                Status::new(StatusCode::ExpectationFailed),
+                "417 Expectation Failed".to_string(),
+                HashMap::new(),
+            ));
+        }
+        // HTTP/1.0 clients don't understand interim responses
+        if req.version == "HTTP/1.0" {
+            return None;
+        }
+        // Requests no route takes are answered by the router's fallbacks
+        // without asking for the body
+        if !router.has_route(&req.method, &req.target) {
+            return None;
+        }
+        if let Ok(writer) = stream.try_clone() {
+            req.body.continue_on_read(Box::new(writer));
+        }
+        None
+    }
+}
+
+async fn send(response: Response, mut stream: TcpStream) {
+    let sent = task::spawn_blocking(move || response.send(&mut stream)).await;
+    match sent {
+        Ok(Err(e)) => eprintln!("Error: {}", e),
+        Err(e) => eprintln!("Error: {}", e),
+        Ok(Ok(())) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract::{Body, Path};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    // Sends `request` to `server` over a loopback connection and returns what
+    // comes back before the server closes it
+    async fn exchange<S: Send + Sync + 'static>(server: HttpServer<S>, request: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let serve = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            server.handle_client(stream).await;
+        });
+        let mut client = tokio::net::TcpStream::connect(addr).await.unwrap();
+        client.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        serve.await.unwrap();
+        response
+    }
+
+    async fn item(prefix: Arc<String>, Path(id): Path<u32>) -> String {
+        format!("{}{}", prefix, id)
+    }
+
+    fn echo(_: &String, Body(body): Body<String>) -> String {
+        body
+    }
+
+    fn server() -> HttpServer<String> {
+        let mut router = Router::new();
+        router.add_route(RequestMethod::GET, "/items/{id}", item);
+        router.add_route(RequestMethod::POST, "/echo", echo);
+        HttpServer::new(Arc::new(RwLock::new(router)), "item ".to_string())
+    }
+
+    #[tokio::test]
+    async fn serves_routed_requests() {
+        let response = exchange(server(), "GET /items/7 HTTP/1.1\r\nHost: x\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.ends_with("\r\n\r\nitem 7"), "{}", response);
+
+        let request = "POST /echo HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\n\r\nhello";
+        let response = exchange(server(), request).await;
+        assert!(response.ends_with("\r\n\r\nhello"), "{}", response);
+
+        let response = exchange(server(), "HEAD /items/7 HTTP/1.1\r\nHost: x\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.contains("Content-Length: 6\r\n"), "{}", response);
+        assert!(response.ends_with("\r\n\r\n"), "{}", response);
+
+        let response = exchange(server(), "GET /nothing HTTP/1.1\r\nHost: x\r\n\r\n").await;
+        assert!(
+            response.starts_with("HTTP/1.1 404 Not Found\r\n"),
+            "{}",
+            response
+        );
+    }
+
+    #[tokio::test]
+    async fn http_1_1_requests_need_a_host() {
+        let response = exchange(server(), "GET /items/7 HTTP/1.1\r\n\r\n").await;
+        assert!(
+            response.starts_with("HTTP/1.1 400 Bad Request\r\n"),
+            "{}",
+            response
+        );
+        let response = exchange(server(), "GET /items/7 HTTP/1.0\r\n\r\n").await;
+        assert!(response.ends_with("\r\n\r\nitem 7"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn parse_errors_go_to_the_handler() {
+        let response = exchange(server(), "NOT HTTP\r\n\r\n").await;
+        assert!(
+            response.starts_with("HTTP/1.1 400 Bad Request\r\n"),
+            "{}",
+            response
+        );
 
-        response.send(&mut stream).unwrap();
+        let server = server().on_parse_error(|e| {
+            Response::builder(
+                Status::new(StatusCode::BadRequest),
+                format!("cannot parse: {}", e.kind()),
+                HashMap::new(),
+            )
+        });
+        let response = exchange(server, "NOT HTTP\r\n\r\n").await;
+        assert!(
+            response.starts_with("HTTP/1.1 400 Bad Request\r\n"),
+            "{}",
+            response
+        );
+        assert!(response.contains("\r\n\r\ncannot parse: "), "{}", response);
     }
 }
@@ -0,0 +1,15 @@
+use crate::config::Config;
+use crate::router::Urls;
+
+// Shared by every handler
+pub struct AppState {
+    pub config: Config,
+    // Where the named routes are, so handlers don't hardcode their URLs
+    pub urls: Urls,
+}
+
+impl AppState {
+    pub fn new(config: Config, urls: Urls) -> AppState {
+        AppState { config, urls }
+    }
+}
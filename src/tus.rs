@@ -0,0 +1,281 @@
+use crate::digest::{self, DigestAlgorithm};
+use crate::files::{self, WriteMode};
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Error, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const TUS_VERSION: &str = "1.0.0";
+pub const TUS_EXTENSIONS: &str = "creation,termination,expiration";
+
+// Upload state lives in this directory under the files root
+pub const STATE_DIR: &str = ".tus";
+
+// Uploads that see no PATCH for this long expire
+const EXPIRY: Duration = Duration::from_secs(24 * 60 * 60);
+
+// A resumable upload (tus 1.0). Received bytes are appended to `<id>.part`
+// and the rest of the state is kept in `<id>.info`, so uploads survive a
+// restart. The offset is the length of the part file.
+pub struct Upload {
+    pub id: String,
+    pub length: u64,
+    pub offset: u64,
+    // URL path of the destination relative to the files root
+    pub destination: String,
+    pub metadata: Option<String>,
+    pub expires: SystemTime,
+    pub complete: bool,
+}
+
+impl Upload {
+    pub fn is_expired(&self) -> bool {
+        !self.complete && SystemTime::now() > self.expires
+    }
+}
+
+pub struct UploadStore {
+    dir: PathBuf,
+}
+
+impl UploadStore {
+    pub fn new(root: &Path) -> UploadStore {
+        UploadStore {
+            dir: root.join(STATE_DIR),
+        }
+    }
+
+    pub fn create(
+        &self,
+        length: u64,
+        destination: String,
+        metadata: Option<String>,
+    ) -> io::Result<Upload> {
+        std::fs::create_dir_all(&self.dir)?;
+        self.remove_expired();
+
+        let upload = Upload {
+            id: new_id(),
+            length,
+            offset: 0,
+            destination,
+            metadata,
+            expires: SystemTime::now() + EXPIRY,
+            complete: false,
+        };
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(self.part_path(&upload.id))?;
+        self.save(&upload)?;
+        Ok(upload)
+    }
+
+    pub fn load(&self, id: &str) -> io::Result<Option<Upload>> {
+        // Ids are hex, which also keeps them from naming other paths
+        if id.is_empty() || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Ok(None);
+        }
+        let info = match std::fs::read_to_string(self.info_path(id)) {
+            Ok(info) => info,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let invalid = || Error::new(ErrorKind::InvalidData, "Invalid upload state");
+        let mut length = None;
+        let mut destination = None;
+        let mut metadata = None;
+        let mut expires = None;
+        let mut complete = false;
+        for line in info.lines() {
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "length" => length = value.parse::<u64>().ok(),
+                "destination" => destination = Some(value.to_string()),
+                "metadata" => metadata = Some(value.to_string()),
+                "expires" => {
+                    expires = value
+                        .parse::<u64>()
+                        .ok()
+                        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+                }
+                "complete" => complete = true,
+                _ => {}
+            }
+        }
+        let length = length.ok_or_else(invalid)?;
+
+        let offset = if complete {
+            length
+        } else {
+            std::fs::metadata(self.part_path(id))?.len()
+        };
+        Ok(Some(Upload {
+            id: id.to_string(),
+            length,
+            offset,
+            destination: destination.ok_or_else(invalid)?,
+            metadata,
+            expires: expires.ok_or_else(invalid)?,
+            complete,
+        }))
+    }
+
+    // Appends `body` to the upload, at most up to its declared length. Bytes
+    // received before an error are kept, so the client can resume after them.
+    pub fn append<R: Read>(&self, upload: &mut Upload, body: R) -> io::Result<u64> {
+        let mut part = OpenOptions::new()
+            .append(true)
+            .open(self.part_path(&upload.id))?;
+        let copied = io::copy(&mut body.take(upload.length - upload.offset), &mut part);
+        part.sync_all()?;
+
+        upload.offset = part.metadata()?.len();
+        upload.expires = SystemTime::now() + EXPIRY;
+        self.save(upload)?;
+        copied
+    }
+
+    // Moves a fully received upload to `destination`, failing with
+    // AlreadyExists rather than replacing a file created there meanwhile. The
+    // state is kept until it expires so clients can still see that the upload
+    // finished.
+    pub fn finish(&self, upload: &mut Upload, destination: &Path) -> io::Result<()> {
+        if let Some(parent) = destination.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // A hard link fails if the destination exists, like create-only uploads
+        let part = self.part_path(&upload.id);
+        std::fs::hard_link(&part, destination)?;
+        std::fs::remove_file(&part)?;
+        upload.complete = true;
+        upload.expires = SystemTime::now() + EXPIRY;
+        self.save(upload)
+    }
+
+    pub fn terminate(&self, id: &str) -> io::Result<()> {
+        match std::fs::remove_file(self.part_path(id)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        std::fs::remove_file(self.info_path(id))
+    }
+
+    // Deletes the state of expired uploads, and of finished ones once clients
+    // no longer need it
+    pub fn remove_expired(&self) {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        let now = SystemTime::now();
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if let Some(id) = name.strip_suffix(".info") {
+                if let Ok(Some(upload)) = self.load(id) {
+                    if now > upload.expires {
+                        let _ = self.terminate(id);
+                    }
+                }
+            }
+        }
+    }
+
+    fn save(&self, upload: &Upload) -> io::Result<()> {
+        let expires = upload
+            .expires
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let mut info = format!(
+            "length {}\ndestination {}\nexpires {}\n",
+            upload.length, upload.destination, expires
+        );
+        if let Some(metadata) = &upload.metadata {
+            info.push_str(&format!("metadata {}\n", metadata));
+        }
+        if upload.complete {
+            info.push_str("complete\n");
+        }
+        files::write_atomic(
+            &self.info_path(&upload.id),
+            &mut info.as_bytes(),
+            WriteMode::Replace,
+        )?;
+        Ok(())
+    }
+
+    fn info_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.info", id))
+    }
+
+    fn part_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.part", id))
+    }
+}
+
+// Marks an upload as being written to until dropped, so two PATCH requests
+// can't append to it at once
+pub struct UploadLock {
+    id: String,
+}
+
+static LOCKED: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+
+impl UploadLock {
+    pub fn acquire(id: &str) -> Option<UploadLock> {
+        let mut locked = LOCKED.lock().unwrap();
+        if !locked.get_or_insert_with(HashSet::new).insert(id.to_string()) {
+            return None;
+        }
+        Some(UploadLock { id: id.to_string() })
+    }
+}
+
+impl Drop for UploadLock {
+    fn drop(&mut self) {
+        if let Some(locked) = LOCKED.lock().unwrap().as_mut() {
+            locked.remove(&self.id);
+        }
+    }
+}
+
+// Looks up a key of an `Upload-Metadata` value, e.g. `filename d29ybGQ=,is_ok`
+pub fn metadata_value(metadata: &str, key: &str) -> Option<String> {
+    metadata
+        .split(',')
+        .filter_map(|pair| {
+            let mut parts = pair.split_whitespace();
+            Some((parts.next()?, parts.next().unwrap_or("")))
+        })
+        .find(|(k, _)| *k == key)
+        .and_then(|(_, value)| digest::base64_decode(value))
+        .and_then(|value| String::from_utf8(value).ok())
+}
+
+// Random bytes from the OS keep upload URLs unguessable; the time, process
+// and counter still tell ids apart should /dev/urandom be unavailable
+fn new_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let mut seed = format!(
+        "{}-{}-{}-",
+        nanos,
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+    .into_bytes();
+    let mut random = [0u8; 16];
+    if let Ok(mut urandom) = File::open("/dev/urandom") {
+        let _ = urandom.read_exact(&mut random);
+    }
+    seed.extend_from_slice(&random);
+    hex::encode(&DigestAlgorithm::Sha256.digest(&seed)[..16])
+}
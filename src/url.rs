@@ -0,0 +1,96 @@
+use std::io::{Error, ErrorKind};
+
+// Decodes %XX escapes. Fails on truncated or non-hex escapes and on results
+// that are not valid UTF-8.
+pub fn percent_decode(input: &str) -> Result<String, Error> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes
+                .get(i + 1..i + 3)
+                .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Invalid percent-encoding"))?;
+            decoded.push(hex);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+// Percent-encodes everything but unreserved characters, so the result is safe
+// as a single path segment or query component.
+pub fn percent_encode(input: &str) -> String {
+    let mut encoded = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+// Splits a query string into decoded key/value pairs, treating `+` as a space.
+// Pairs that fail to decode are skipped.
+pub fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .filter_map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let key = percent_decode(&key.replace('+', " ")).ok()?;
+            let value = percent_decode(&value.replace('+', " ")).ok()?;
+            Some((key, value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_escapes() {
+        assert_eq!(percent_decode("a%20b").unwrap(), "a b");
+        assert_eq!(percent_decode("%2e%2E%2f").unwrap(), "../");
+        assert_eq!(percent_decode("%E2%82%AC").unwrap(), "€");
+        assert_eq!(percent_decode("+").unwrap(), "+");
+        assert_eq!(percent_decode("%00").unwrap(), "\0");
+    }
+
+    #[test]
+    fn refuses_malformed_escapes() {
+        for input in ["%", "%2", "%zz", "%+1", "%-1", "a%2", "%ff", "%C3"] {
+            assert!(percent_decode(input).is_err(), "{} should fail", input);
+        }
+    }
+
+    #[test]
+    fn encodes_all_but_unreserved() {
+        assert_eq!(percent_encode("a b/c?d"), "a%20b%2Fc%3Fd");
+        assert_eq!(percent_encode("A-z_0.9~"), "A-z_0.9~");
+        assert_eq!(percent_decode(&percent_encode("€ ../x")).unwrap(), "€ ../x");
+    }
+
+    #[test]
+    fn parses_queries() {
+        assert_eq!(
+            parse_query("a=1&b=x+y&a=%32&flag&&c=%zz"),
+            vec![
+                ("a".to_string(), "1".to_string()),
+                ("b".to_string(), "x y".to_string()),
+                ("a".to_string(), "2".to_string()),
+                ("flag".to_string(), String::new()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,133 @@
+use crate::router::Router;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+// Which `Host` names a virtual host answers: one exact name, or every
+// subdomain of a name for `*.example.test`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HostPattern {
+    Exact(String),
+    // The suffix subdomains end with, including the leading dot
+    Wildcard(String),
+}
+
+impl HostPattern {
+    pub fn parse(pattern: &str) -> HostPattern {
+        let pattern = pattern.trim().to_lowercase();
+        match pattern.strip_prefix('*') {
+            Some(suffix) if suffix.starts_with('.') => HostPattern::Wildcard(suffix.to_string()),
+            _ => HostPattern::Exact(pattern),
+        }
+    }
+
+    // `host` is a name as returned by `host_name`
+    pub fn matches(&self, host: &str) -> bool {
+        match self {
+            HostPattern::Exact(name) => name == host,
+            HostPattern::Wildcard(suffix) => host.len() > suffix.len() && host.ends_with(suffix),
+        }
+    }
+}
+
+// A router with the state its handlers get, for one or more host names
+pub struct VirtualHost<S> {
+    pub router: Arc<RwLock<Router<S>>>,
+    pub state: Arc<S>,
+}
+
+// The host that answers requests for `host`: one with that exact name, else
+// the wildcard with the longest suffix
+pub fn select<'a, S>(
+    hosts: &'a [(HostPattern, VirtualHost<S>)],
+    host: &str,
+) -> Option<&'a VirtualHost<S>> {
+    let name = host_name(host);
+    let exact = hosts.iter().find(|(pattern, _)| match pattern {
+        HostPattern::Exact(_) => pattern.matches(&name),
+        HostPattern::Wildcard(_) => false,
+    });
+    let wildcard = || {
+        hosts
+            .iter()
+            .filter(|(pattern, _)| match pattern {
+                HostPattern::Exact(_) => false,
+                HostPattern::Wildcard(_) => pattern.matches(&name),
+            })
+            .max_by_key(|(pattern, _)| match pattern {
+                HostPattern::Exact(name) | HostPattern::Wildcard(name) => name.len(),
+            })
+    };
+    exact.or_else(wildcard).map(|(_, vhost)| vhost)
+}
+
+// The name in a `Host` header value, without the port or a trailing dot
+pub fn host_name(host: &str) -> String {
+    let host = host.trim();
+    let name = match host.strip_prefix('[') {
+        // An IPv6 literal keeps its brackets
+        Some(rest) => &host[..rest.find(']').map(|end| end + 2).unwrap_or(host.len())],
+        None => host.split(':').next().unwrap_or_default(),
+    };
+    name.trim_end_matches('.').to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_names() {
+        assert_eq!(host_name("Example.TEST"), "example.test");
+        assert_eq!(host_name("example.test:8080"), "example.test");
+        assert_eq!(host_name(" example.test.:80 "), "example.test");
+        assert_eq!(host_name("127.0.0.1:4221"), "127.0.0.1");
+        assert_eq!(host_name("[::1]:4221"), "[::1]");
+        assert_eq!(host_name("[::1]"), "[::1]");
+        assert_eq!(host_name(":80"), "");
+    }
+
+    #[test]
+    fn patterns() {
+        let exact = HostPattern::parse(" Example.test ");
+        assert_eq!(exact, HostPattern::Exact("example.test".to_string()));
+        assert!(exact.matches("example.test"));
+        assert!(!exact.matches("www.example.test"));
+
+        let wildcard = HostPattern::parse("*.example.test");
+        assert_eq!(wildcard, HostPattern::Wildcard(".example.test".to_string()));
+        assert!(wildcard.matches("www.example.test"));
+        assert!(wildcard.matches("a.b.example.test"));
+        assert!(!wildcard.matches("example.test"));
+        assert!(!wildcard.matches(".example.test"));
+        assert!(!wildcard.matches("wwwexample.test"));
+
+        // Only `*.` starts a wildcard
+        assert_eq!(
+            HostPattern::parse("*example.test"),
+            HostPattern::Exact("*example.test".to_string())
+        );
+    }
+
+    #[test]
+    fn selects_exact_names_then_the_longest_wildcard() {
+        let host = |pattern: &str, state: u8| {
+            let vhost = VirtualHost {
+                router: Arc::new(RwLock::new(Router::new())),
+                state: Arc::new(state),
+            };
+            (HostPattern::parse(pattern), vhost)
+        };
+        let hosts = [
+            host("*.test", 1),
+            host("*.example.test", 2),
+            host("www.example.test", 3),
+        ];
+        let selected = |name: &str| select(&hosts, name).map(|vhost| *vhost.state);
+        assert_eq!(selected("WWW.example.test:8080"), Some(3));
+        assert_eq!(selected("api.example.test"), Some(2));
+        assert_eq!(selected("example.test"), Some(1));
+        assert_eq!(selected("other.test."), Some(1));
+        assert_eq!(selected("test"), None);
+        assert_eq!(selected("example.org"), None);
+    }
+}
@@ -0,0 +1,171 @@
+use crate::date::format_http_date;
+use crate::files;
+use crate::http::{ContentType, StatusCode};
+use crate::listing::escape_html;
+use std::fmt::Write;
+use std::fs::Metadata;
+use std::io;
+use std::path::Path;
+
+// The live properties reported for every resource
+const PROPERTIES: [&str; 6] = [
+    "displayname",
+    "getcontentlength",
+    "getcontenttype",
+    "getetag",
+    "getlastmodified",
+    "resourcetype",
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Propfind {
+    AllProp,
+    PropName,
+    Prop(Vec<String>),
+}
+
+// Parses a PROPFIND body. An empty body asks for all properties. Only the
+// local names of elements matter, as every property we know is in DAV:.
+pub fn parse_propfind(body: &str) -> Option<Propfind> {
+    if body.trim().is_empty() {
+        return Some(Propfind::AllProp);
+    }
+
+    let mut seen_propfind = false;
+    let mut in_prop = false;
+    let mut names = Vec::new();
+    let mut rest = body;
+    while let Some(start) = rest.find('<') {
+        let end = rest[start..].find('>')? + start;
+        let tag = &rest[start + 1..end];
+        rest = &rest[end + 1..];
+        // Declarations, comments and processing instructions
+        if tag.starts_with('?') || tag.starts_with('!') {
+            continue;
+        }
+
+        let closing = tag.starts_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or("");
+        let local = name.rsplit(':').next().unwrap_or(name);
+        match (local, closing) {
+            ("propfind", false) => seen_propfind = true,
+            ("allprop", false) => return seen_propfind.then_some(Propfind::AllProp),
+            ("propname", false) => return seen_propfind.then_some(Propfind::PropName),
+            ("prop", false) if !tag.ends_with('/') => in_prop = true,
+            ("prop", true) => in_prop = false,
+            (local, false) if in_prop => names.push(local.to_string()),
+            _ => {}
+        }
+    }
+    seen_propfind.then_some(Propfind::Prop(names))
+}
+
+// One `<D:response>` of a multistatus body describing `path`, served at `href`
+pub fn propfind_response(
+    href: &str,
+    path: &Path,
+    metadata: &Metadata,
+    propfind: &Propfind,
+) -> String {
+    let mut found = String::new();
+    let mut missing = String::new();
+    match propfind {
+        Propfind::AllProp => {
+            for name in PROPERTIES {
+                if let Some(value) = property(name, path, metadata) {
+                    write_property(&mut found, name, &value);
+                }
+            }
+        }
+        Propfind::PropName => {
+            for name in PROPERTIES {
+                if property(name, path, metadata).is_some() {
+                    write_property(&mut found, name, "");
+                }
+            }
+        }
+        Propfind::Prop(names) => {
+            for name in names {
+                match property(name, path, metadata) {
+                    Some(value) => write_property(&mut found, name, &value),
+                    None => write_property(&mut missing, name, ""),
+                }
+            }
+        }
+    }
+
+    let mut response = format!("<D:response><D:href>{}</D:href>", escape_html(href));
+    for (props, status) in [(found, StatusCode::Ok), (missing, StatusCode::NotFound)] {
+        if !props.is_empty() {
+            let _ = write!(
+                response,
+                "<D:propstat><D:prop>{}</D:prop><D:status>HTTP/1.1 {} {}</D:status></D:propstat>",
+                props,
+                status.to_u16(),
+                status.to_reason_phrase()
+            );
+        }
+    }
+    response.push_str("</D:response>");
+    response
+}
+
+pub fn multistatus(responses: &[String]) -> String {
+    let mut body = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    body.push_str("<D:multistatus xmlns:D=\"DAV:\">");
+    for response in responses {
+        body.push_str(response);
+    }
+    body.push_str("</D:multistatus>\n");
+    body
+}
+
+// The value of a live property as XML, or None if the resource has none
+fn property(name: &str, path: &Path, metadata: &Metadata) -> Option<String> {
+    let is_dir = metadata.is_dir();
+    match name {
+        "displayname" => path
+            .file_name()
+            .map(|name| escape_html(&name.to_string_lossy())),
+        "getcontentlength" if !is_dir => Some(metadata.len().to_string()),
+        "getcontenttype" if !is_dir => Some(ContentType::from_path(path).to_string()),
+        "getetag" if !is_dir => Some(escape_html(&files::etag(metadata, None))),
+        "getlastmodified" => metadata.modified().ok().map(format_http_date),
+        "resourcetype" if is_dir => Some("<D:collection/>".to_string()),
+        "resourcetype" => Some(String::new()),
+        _ => None,
+    }
+}
+
+fn write_property(out: &mut String, name: &str, value: &str) {
+    if value.is_empty() {
+        let _ = write!(out, "<D:{}/>", name);
+    } else {
+        let _ = write!(out, "<D:{}>{}</D:{}>", name, value, name);
+    }
+}
+
+// Copies a file, or a directory with everything below it when `recursive`.
+// Symlinks below the top level are skipped rather than followed, so a copy
+// never pulls in anything from outside the tree.
+pub fn copy(from: &Path, to: &Path, recursive: bool) -> io::Result<()> {
+    if !from.is_dir() {
+        return std::fs::copy(from, to).map(|_| ());
+    }
+    std::fs::create_dir(to)?;
+    if !recursive {
+        return Ok(());
+    }
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        if entry.file_type()?.is_symlink() {
+            continue;
+        }
+        copy(&entry.path(), &to.join(entry.file_name()), true)?;
+    }
+    Ok(())
+}